@@ -0,0 +1,185 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:">
+  <d:prop>
+    <d:getetag/>
+  </d:prop>
+</d:propfind>"#;
+
+/// The resource changed on the server since its ETag was read.
+#[derive(Debug)]
+pub struct PreconditionFailed;
+
+impl Display for PreconditionFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Resource changed on the server")
+    }
+}
+
+impl Error for PreconditionFailed {}
+
+/// A calendar resource, `href` is the path the server reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resource {
+    pub href: String,
+    pub etag: String,
+}
+
+/// A CalDAV calendar collection holding VTODOs.
+pub struct Client {
+    url: String,
+    origin: String,
+    agent: ureq::Agent,
+    authorization: Option<String>,
+}
+
+impl Client {
+    pub fn new(url: &str) -> Result<Self> {
+        let url = format!("{}/", url.trim_end_matches('/'));
+
+        let origin = match url.split_once("://") {
+            Some((scheme, rest)) => match rest.find('/') {
+                Some(i) => format!("{}://{}", scheme, &rest[..i]),
+                None => url.clone(),
+            },
+            None => bail!("Invalid CalDAV URL: {}", url),
+        };
+
+        Ok(Self {
+            url,
+            origin,
+            agent: ureq::agent(),
+            authorization: None,
+        })
+    }
+
+    /// Uses HTTP basic authentication.
+    pub fn with_credentials(mut self, user: &str, password: &str) -> Self {
+        let credentials = STANDARD.encode(format!("{}:{}", user, password));
+
+        self.authorization = Some(format!("Basic {}", credentials));
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Where a task created by tors is stored.
+    pub fn href(&self, id: &str) -> String {
+        format!("{}{}.ics", &self.url[self.origin.len()..], id)
+    }
+
+    /// Lists every resource of the collection.
+    pub fn list(&self) -> Result<Vec<Resource>> {
+        let response = self
+            .request("PROPFIND", &self.url)
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(PROPFIND_BODY)
+            .map_err(status_error)?;
+
+        let body = response.into_string()?;
+        let document = roxmltree::Document::parse(&body).context("Invalid PROPFIND response")?;
+
+        let resources = document
+            .descendants()
+            .filter(|n| n.has_tag_name(("DAV:", "response")))
+            .filter_map(|response| {
+                let href = dav_text(response, "href")?;
+                let etag = dav_text(response, "getetag")?;
+
+                (!href.ends_with('/')).then(|| Resource {
+                    href: href.to_string(),
+                    etag: etag.to_string(),
+                })
+            })
+            .collect();
+
+        Ok(resources)
+    }
+
+    /// Returns the body and the ETag of a resource.
+    pub fn get(&self, href: &str) -> Result<(String, Option<String>)> {
+        let response = self
+            .request("GET", &self.resolve(href))
+            .call()
+            .map_err(status_error)?;
+        let etag = response.header("ETag").map(String::from);
+
+        let mut body = String::new();
+        response.into_reader().read_to_string(&mut body)?;
+
+        Ok((body, etag))
+    }
+
+    /// Creates a resource, or replaces it if it still has `etag`. Returns the new ETag.
+    pub fn put(&self, href: &str, body: &str, etag: Option<&str>) -> Result<Option<String>> {
+        let request = self
+            .request("PUT", &self.resolve(href))
+            .set("Content-Type", "text/calendar; charset=utf-8");
+
+        let request = match etag {
+            Some(etag) => request.set("If-Match", etag),
+            None => request.set("If-None-Match", "*"),
+        };
+
+        let response = request.send_string(body).map_err(status_error)?;
+
+        Ok(response.header("ETag").map(String::from))
+    }
+
+    /// Deletes a resource if it still has `etag`.
+    pub fn delete(&self, href: &str, etag: &str) -> Result<()> {
+        match self
+            .request("DELETE", &self.resolve(href))
+            .set("If-Match", etag)
+            .call()
+        {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(()),
+            Err(err) => Err(status_error(err)),
+        }
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self.agent.request(method, url);
+
+        match &self.authorization {
+            Some(authorization) => request.set("Authorization", authorization),
+            None => request,
+        }
+    }
+
+    fn resolve(&self, href: &str) -> String {
+        if href.contains("://") {
+            href.to_string()
+        } else if href.starts_with('/') {
+            format!("{}{}", self.origin, href)
+        } else {
+            format!("{}{}", self.url, href)
+        }
+    }
+}
+
+fn dav_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.descendants()
+        .find(|n| n.has_tag_name(("DAV:", name)))?
+        .text()
+        .map(str::trim)
+}
+
+fn status_error(err: ureq::Error) -> anyhow::Error {
+    match err {
+        ureq::Error::Status(412, _) => PreconditionFailed.into(),
+        ureq::Error::Status(code, response) => {
+            anyhow::anyhow!("CalDAV server error {} for {}", code, response.get_url())
+        }
+        err => err.into(),
+    }
+}
@@ -0,0 +1,231 @@
+//! Two-way sync of tasks with a CalDAV calendar.
+//!
+//! Every task is a VTODO resource whose `UID` is the task id. The ETag and
+//! href of each resource and a fingerprint of each task as of the last sync
+//! are kept in the `caldav:<url>` sync state of the database; they tell which
+//! side changed a task. A task changed on both sides is reported as a
+//! conflict and left as it is on each side, as is a task whose resource
+//! can't be read, like one repeating in a way tors doesn't support. Archived
+//! tasks are removed from the server.
+
+mod client;
+
+pub use crate::client::{Client, PreconditionFailed, Resource};
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use tors_database::{Database, SyncState, Task, TaskId, TaskState, Todo};
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub to_database: usize,
+    pub to_server: usize,
+    /// Tasks changed on both sides since the last sync, left untouched.
+    pub conflicts: Vec<TaskId>,
+    /// Resources that couldn't be read, by href with the reason, left untouched.
+    pub skipped: Vec<(String, String)>,
+}
+
+/// A task as of the last sync.
+struct Base {
+    href: String,
+    fingerprint: String,
+    etag: String,
+}
+
+impl Base {
+    fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.splitn(3, '\n');
+
+        Some(Self {
+            href: parts.next()?.to_string(),
+            fingerprint: parts.next()?.to_string(),
+            etag: parts.next()?.to_string(),
+        })
+    }
+
+    fn save(&self, state: &SyncState, id: &str) -> Result<()> {
+        state.set(
+            id,
+            &format!("{}\n{}\n{}", self.href, self.fingerprint, self.etag),
+        )
+    }
+}
+
+pub fn sync(database: &Database, client: &Client, now: NaiveDateTime) -> Result<SyncReport> {
+    database.batch(|| {
+        let state = database.sync_state(&format!("caldav:{}", client.url()))?;
+
+        let mut tasks = database
+            .list_tasks()?
+            .into_iter()
+            .filter(|(_, task)| task.state(now) != TaskState::Archived)
+            .collect::<HashMap<_, _>>();
+        let mut bases = state
+            .ids()?
+            .into_iter()
+            .filter_map(|id| {
+                let base = Base::parse(&state.get(&id).ok()??)?;
+                Some((id, base))
+            })
+            .collect::<HashMap<_, _>>();
+        let hrefs = bases
+            .iter()
+            .map(|(id, base)| (base.href.clone(), id.clone()))
+            .collect::<HashMap<_, _>>();
+
+        let mut unchanged = HashSet::new();
+        let mut seen = HashSet::new();
+        let mut report = SyncReport::default();
+
+        for resource in client.list()? {
+            if let Some(id) = hrefs.get(&resource.href) {
+                if bases[id].etag == resource.etag {
+                    unchanged.insert(id.clone());
+                    seen.insert(id.clone());
+                    continue;
+                }
+            }
+
+            let (body, etag) = client.get(&resource.href)?;
+            let todo = match Todo::parse_calendar(&body) {
+                Ok(todos) => match todos.into_iter().next() {
+                    Some(todo) => todo,
+                    None => continue,
+                },
+                Err(err) => {
+                    if let Some(id) = hrefs.get(&resource.href) {
+                        tasks.remove(id);
+                        bases.remove(id);
+                    }
+
+                    report.skipped.push((resource.href, format!("{:#}", err)));
+                    continue;
+                }
+            };
+            let id = todo.uid.clone();
+
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+
+            let base = bases.remove(&id);
+            let synced = |task: &Task| Base {
+                href: resource.href.clone(),
+                fingerprint: fingerprint(&id, task),
+                etag: etag.clone().unwrap_or_else(|| resource.etag.clone()),
+            };
+
+            match tasks.remove(&id) {
+                Some(task) => {
+                    let mut merged = task.clone();
+                    todo.update(&mut merged);
+
+                    if fingerprint(&id, &merged) == fingerprint(&id, &task) {
+                        synced(&task).save(&state, &id)?;
+                    } else if base.is_some_and(|b| b.fingerprint == fingerprint(&id, &task)) {
+                        database.update_task(&id, &merged)?;
+                        synced(&merged).save(&state, &id)?;
+                        report.to_database += 1;
+                    } else {
+                        report.conflicts.push(id);
+                    }
+                }
+                None if base.is_none() => {
+                    let task = todo.to_task(now);
+
+                    database.insert_task(&id, &task)?;
+                    synced(&task).save(&state, &id)?;
+                    report.to_database += 1;
+                }
+                None => report.conflicts.push(id),
+            }
+        }
+
+        let mut remaining = tasks.into_iter().collect::<Vec<_>>();
+        remaining.sort_unstable_by_key(|(_, task)| task.creation_date);
+
+        for (id, task) in remaining {
+            let fingerprint = fingerprint(&id, &task);
+
+            match bases.remove(&id) {
+                None => {
+                    let href = client.href(&id);
+                    let etag = push(client, &href, &id, &task, None, now)?;
+
+                    Base {
+                        href,
+                        fingerprint,
+                        etag,
+                    }
+                    .save(&state, &id)?;
+                    report.to_server += 1;
+                }
+                Some(base) if base.fingerprint == fingerprint => {
+                    if !unchanged.contains(&id) {
+                        database.delete_task(&id)?;
+                        state.remove(&id)?;
+                        report.to_database += 1;
+                    }
+                }
+                Some(base) if unchanged.contains(&id) => {
+                    match push(client, &base.href, &id, &task, Some(&base.etag), now) {
+                        Ok(etag) => {
+                            Base {
+                                etag,
+                                fingerprint,
+                                ..base
+                            }
+                            .save(&state, &id)?;
+                            report.to_server += 1;
+                        }
+                        Err(err) if err.is::<PreconditionFailed>() => report.conflicts.push(id),
+                        Err(err) => return Err(err),
+                    }
+                }
+                Some(_) => report.conflicts.push(id),
+            }
+        }
+
+        for (id, base) in bases {
+            if unchanged.contains(&id) {
+                match client.delete(&base.href, &base.etag) {
+                    Ok(()) => report.to_server += 1,
+                    Err(err) if err.is::<PreconditionFailed>() => {
+                        report.conflicts.push(id);
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+
+            state.remove(&id)?;
+        }
+
+        Ok(report)
+    })
+}
+
+/// Uploads a task, returning its new ETag.
+fn push(
+    client: &Client,
+    href: &str,
+    id: &str,
+    task: &Task,
+    etag: Option<&str>,
+    now: NaiveDateTime,
+) -> Result<String> {
+    let body = Todo::write_calendar(&[Todo::from_task(id, task)], now);
+
+    match client.put(href, &body, etag)? {
+        Some(etag) => Ok(etag),
+        None => Ok(client.get(href)?.1.unwrap_or_default()),
+    }
+}
+
+/// Changes whenever a synced field of the task changes.
+fn fingerprint(id: &str, task: &Task) -> String {
+    hex::encode(Sha256::digest(format!("{:?}", Todo::from_task(id, task))))
+}
@@ -0,0 +1,149 @@
+//! A minimal Radicale-like CalDAV server: one collection at `/user/tasks/`
+//! answering PROPFIND, GET, PUT and DELETE with ETag preconditions.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Request, Response};
+
+pub const COLLECTION: &str = "/user/tasks/";
+
+#[derive(Default)]
+struct Store {
+    resources: BTreeMap<String, (String, String)>,
+    revision: u32,
+}
+
+impl Store {
+    fn save(&mut self, href: &str, body: String) -> String {
+        self.revision += 1;
+        let etag = format!("\"{}\"", self.revision);
+
+        self.resources
+            .insert(href.to_string(), (etag.clone(), body));
+        etag
+    }
+}
+
+pub struct Server {
+    pub url: String,
+    store: Arc<Mutex<Store>>,
+}
+
+impl Server {
+    pub fn start() -> Self {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        let store = Arc::new(Mutex::new(Store::default()));
+
+        let shared = store.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                handle(&shared, request);
+            }
+        });
+
+        Self {
+            url: format!("http://127.0.0.1:{}{}", port, COLLECTION),
+            store,
+        }
+    }
+
+    /// Bodies of every resource, like another client would see them.
+    pub fn bodies(&self) -> BTreeMap<String, String> {
+        let store = self.store.lock().unwrap();
+
+        store
+            .resources
+            .iter()
+            .map(|(href, (_, body))| (href.clone(), body.clone()))
+            .collect()
+    }
+
+    /// Changes a resource as another client would.
+    pub fn put(&self, href: &str, body: &str) {
+        self.store.lock().unwrap().save(href, body.to_string());
+    }
+
+    pub fn delete(&self, href: &str) {
+        self.store.lock().unwrap().resources.remove(href);
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+fn handle(store: &Mutex<Store>, mut request: Request) {
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body).unwrap();
+
+    let href = request.url().to_string();
+    let if_match = header(&request, "If-Match").map(String::from);
+    let if_none_match = header(&request, "If-None-Match").map(String::from);
+
+    let mut store = store.lock().unwrap();
+    let current = store.resources.get(&href).map(|(etag, _)| etag.clone());
+
+    let response = match request.method().as_str() {
+        "PROPFIND" if href == COLLECTION => {
+            let mut xml = String::from(r#"<?xml version="1.0"?><multistatus xmlns="DAV:">"#);
+            xml.push_str(&format!(
+                "<response><href>{}</href><propstat><prop><resourcetype><collection/></resourcetype></prop></propstat></response>",
+                COLLECTION
+            ));
+
+            for (href, (etag, _)) in &store.resources {
+                xml.push_str(&format!(
+                    "<response><href>{}</href><propstat><prop><getetag>{}</getetag></prop><status>HTTP/1.1 200 OK</status></propstat></response>",
+                    href,
+                    etag.replace('"', "&quot;")
+                ));
+            }
+
+            xml.push_str("</multistatus>");
+            Response::from_string(xml).with_status_code(207)
+        }
+        "GET" => match store.resources.get(&href) {
+            Some((etag, body)) => Response::from_string(body.clone())
+                .with_header(Header::from_bytes("ETag", etag.as_bytes()).unwrap()),
+            None => Response::from_string("").with_status_code(404),
+        },
+        "PUT" => {
+            let allowed = match (&if_match, &if_none_match, &current) {
+                (Some(expected), _, Some(etag)) => expected == etag,
+                (Some(_), _, None) => false,
+                (None, Some(_), Some(_)) => false,
+                _ => true,
+            };
+
+            if allowed {
+                let etag = store.save(&href, body);
+
+                Response::from_string("")
+                    .with_status_code(if current.is_some() { 204 } else { 201 })
+                    .with_header(Header::from_bytes("ETag", etag.as_bytes()).unwrap())
+            } else {
+                Response::from_string("").with_status_code(412)
+            }
+        }
+        "DELETE" => match (&if_match, &current) {
+            (_, None) => Response::from_string("").with_status_code(404),
+            (Some(expected), Some(etag)) if expected != etag => {
+                Response::from_string("").with_status_code(412)
+            }
+            _ => {
+                store.resources.remove(&href);
+                Response::from_string("").with_status_code(204)
+            }
+        },
+        _ => Response::from_string("").with_status_code(405),
+    };
+
+    drop(store);
+    request.respond(response).unwrap();
+}
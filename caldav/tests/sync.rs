@@ -0,0 +1,191 @@
+mod server;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use server::Server;
+use tors_caldav::{sync, Client};
+use tors_database::{Database, MemoryStore, Task};
+
+fn now() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2022, 9, 10)
+        .unwrap()
+        .and_hms_opt(8, 0, 0)
+        .unwrap()
+}
+
+fn database() -> Database {
+    Database::temporary()
+        .unwrap()
+        .with_task_store(MemoryStore::default())
+}
+
+fn task(title: &str) -> Task {
+    let mut task = Task {
+        title: title.to_string(),
+        creation_date: now(),
+        ..Default::default()
+    };
+    task.preferences.expire = now() + chrono::Duration::days(1);
+
+    task
+}
+
+#[test]
+fn pushes_and_pulls() {
+    let server = Server::start();
+    let client = Client::new(&server.url).unwrap();
+
+    let laptop = database();
+    let id = laptop.create_task(&task("Water")).unwrap();
+
+    let report = sync(&laptop, &client, now()).unwrap();
+    assert_eq!((report.to_database, report.to_server), (0, 1));
+
+    let href = format!("{}{}.ics", server::COLLECTION, id);
+    assert!(server.bodies()[&href].contains("SUMMARY:Water"));
+
+    let phone = database();
+    let report = sync(&phone, &client, now()).unwrap();
+    assert_eq!((report.to_database, report.to_server), (1, 0));
+    assert_eq!(phone.get_task(&id).unwrap().unwrap().title, "Water");
+
+    let report = sync(&laptop, &client, now()).unwrap();
+    assert_eq!((report.to_database, report.to_server), (0, 0));
+    assert!(report.conflicts.is_empty());
+}
+
+#[test]
+fn updates_both_sides() {
+    let server = Server::start();
+    let client = Client::new(&server.url).unwrap();
+    let database = database();
+
+    let water = database.create_task(&task("Water")).unwrap();
+    let call = database.create_task(&task("Call")).unwrap();
+    sync(&database, &client, now()).unwrap();
+
+    let href = format!("{}{}.ics", server::COLLECTION, water);
+    server.put(
+        &href,
+        &server.bodies()[&href].replace("SUMMARY:Water", "SUMMARY:Watered"),
+    );
+
+    let mut task = database.get_task(&call).unwrap().unwrap();
+    task.title = "Called".to_string();
+    database.update_task(&call, &task).unwrap();
+
+    let report = sync(&database, &client, now()).unwrap();
+    assert_eq!((report.to_database, report.to_server), (1, 1));
+    assert_eq!(database.get_task(&water).unwrap().unwrap().title, "Watered");
+
+    let href = format!("{}{}.ics", server::COLLECTION, call);
+    assert!(server.bodies()[&href].contains("SUMMARY:Called"));
+}
+
+#[test]
+fn reports_conflicts() {
+    let server = Server::start();
+    let client = Client::new(&server.url).unwrap();
+    let database = database();
+
+    let id = database.create_task(&task("Water")).unwrap();
+    sync(&database, &client, now()).unwrap();
+
+    let href = format!("{}{}.ics", server::COLLECTION, id);
+    server.put(
+        &href,
+        &server.bodies()[&href].replace("SUMMARY:Water", "SUMMARY:Remote"),
+    );
+
+    let mut task = database.get_task(&id).unwrap().unwrap();
+    task.title = "Local".to_string();
+    database.update_task(&id, &task).unwrap();
+
+    let report = sync(&database, &client, now()).unwrap();
+    assert_eq!(report.conflicts, vec![id.clone()]);
+    assert_eq!(database.get_task(&id).unwrap().unwrap().title, "Local");
+    assert!(server.bodies()[&href].contains("SUMMARY:Remote"));
+}
+
+#[test]
+fn deletes_both_sides() {
+    let server = Server::start();
+    let client = Client::new(&server.url).unwrap();
+    let database = database();
+
+    let water = database.create_task(&task("Water")).unwrap();
+    let call = database.create_task(&task("Call")).unwrap();
+    sync(&database, &client, now()).unwrap();
+
+    server.delete(&format!("{}{}.ics", server::COLLECTION, water));
+    database.delete_task(&call).unwrap();
+
+    let report = sync(&database, &client, now()).unwrap();
+    assert_eq!((report.to_database, report.to_server), (1, 1));
+    assert!(database.list_tasks().unwrap().is_empty());
+    assert!(server.bodies().is_empty());
+
+    let report = sync(&database, &client, now()).unwrap();
+    assert_eq!((report.to_database, report.to_server), (0, 0));
+}
+
+#[test]
+fn imports_tasks_from_other_clients() {
+    let server = Server::start();
+    let client = Client::new(&server.url).unwrap();
+    let database = database();
+
+    server.put(
+        "/user/tasks/phone-1.ics",
+        "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VTODO\r\nUID:phone-1@example.com\r\n\
+         SUMMARY:Buy milk\r\nDUE;VALUE=DATE:20220911\r\nEND:VTODO\r\nEND:VCALENDAR\r\n",
+    );
+
+    let report = sync(&database, &client, now()).unwrap();
+    assert_eq!(report.to_database, 1);
+
+    let mut task = database.get_task("phone-1@example.com").unwrap().unwrap();
+    assert_eq!(task.title, "Buy milk");
+
+    task.set_done(true, now());
+    database.update_task("phone-1@example.com", &task).unwrap();
+
+    let report = sync(&database, &client, now()).unwrap();
+    assert_eq!(report.to_server, 1);
+    assert!(server.bodies()["/user/tasks/phone-1.ics"].contains("STATUS:COMPLETED"));
+}
+
+#[test]
+fn skips_unsupported_tasks() {
+    let server = Server::start();
+    let client = Client::new(&server.url).unwrap();
+    let database = database();
+
+    server.put(
+        "/user/tasks/phone-1.ics",
+        "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VTODO\r\nUID:phone-1@example.com\r\n\
+         SUMMARY:Birthday\r\nRRULE:FREQ=YEARLY\r\nEND:VTODO\r\nEND:VCALENDAR\r\n",
+    );
+    server.put(
+        "/user/tasks/phone-2.ics",
+        "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VTODO\r\nUID:phone-2@example.com\r\n\
+         SUMMARY:Buy milk\r\nEND:VTODO\r\nEND:VCALENDAR\r\n",
+    );
+
+    let report = sync(&database, &client, now()).unwrap();
+    assert_eq!((report.to_database, report.to_server), (1, 0));
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].0, "/user/tasks/phone-1.ics");
+    assert!(database.get_task("phone-1@example.com").unwrap().is_none());
+    assert!(server.bodies()["/user/tasks/phone-1.ics"].contains("FREQ=YEARLY"));
+
+    let href = "/user/tasks/phone-2.ics";
+    server.put(
+        href,
+        &server.bodies()[href].replace("END:VTODO", "RRULE:FREQ=YEARLY\r\nEND:VTODO"),
+    );
+
+    let report = sync(&database, &client, now()).unwrap();
+    assert_eq!((report.to_database, report.to_server), (0, 0));
+    assert_eq!(report.skipped.len(), 2);
+    assert!(database.get_task("phone-2@example.com").unwrap().is_some());
+}
@@ -1,7 +1,6 @@
-use phf::phf_map;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Achievements {
     FirstTask,
     MissionComplete,
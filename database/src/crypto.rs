@@ -0,0 +1,302 @@
+use crate::Database;
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use sled::transaction::TransactionError;
+use sled::{Transactional, Tree};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+const ENCRYPTION_KEY: &str = "encryption";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Plaintext of the key-check record, used to detect a wrong passphrase.
+const KEY_CHECK: &[u8] = b"tors";
+
+#[derive(Debug)]
+pub struct WrongPassphrase;
+
+impl Display for WrongPassphrase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Wrong passphrase")
+    }
+}
+
+impl Error for WrongPassphrase {}
+
+/// Stored unencrypted in the meta tree of an encrypted database.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Header {
+    salt: [u8; SALT_LEN],
+    key_check: Vec<u8>,
+}
+
+pub(crate) struct Cipher {
+    aead: XChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// Derives a key with a fresh salt.
+    pub(crate) fn create(passphrase: &str) -> Result<(Self, Header)> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        let cipher = Self::derive(passphrase, &salt)?;
+        let key_check = cipher.encrypt(KEY_CHECK)?;
+
+        Ok((cipher, Header { salt, key_check }))
+    }
+
+    /// Derives the key for an existing header, failing with
+    /// [`WrongPassphrase`] if it doesn't match.
+    pub(crate) fn unlock(passphrase: &str, header: &Header) -> Result<Self> {
+        let cipher = Self::derive(passphrase, &header.salt)?;
+
+        match cipher.decrypt(&header.key_check) {
+            Ok(check) if check == KEY_CHECK => Ok(cipher),
+            _ => Err(WrongPassphrase)?,
+        }
+    }
+
+    fn derive(passphrase: &str, salt: &[u8]) -> Result<Self> {
+        let mut key = [0; 32];
+
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| anyhow!("Failed derive key: {}", e))?;
+
+        Ok(Self {
+            aead: XChaCha20Poly1305::new(&key.into()),
+        })
+    }
+
+    /// Returns the random nonce followed by the ciphertext.
+    pub(crate) fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .aead
+            .encrypt(&nonce, data)
+            .map_err(|_| anyhow!("Failed encrypt value"))?;
+
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub(crate) fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            bail!("Encrypted value is too short")
+        }
+
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+
+        self.aead
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Failed decrypt value"))
+    }
+}
+
+impl Database {
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn is_encrypted(&self) -> Result<bool> {
+        Ok(self.meta.contains_key(ENCRYPTION_KEY)?)
+    }
+
+    /// Unlocks an encrypted database opened with [`Database::open`].
+    pub fn unlock(&mut self, passphrase: &str) -> Result<()> {
+        if !self.locked {
+            bail!("Database is not locked")
+        }
+
+        let header = self.header()?.with_context(|| "Not found encryption header")?;
+
+        self.codec.set_cipher(Some(Cipher::unlock(passphrase, &header)?));
+        self.locked = false;
+
+        self.prepare()
+    }
+
+    pub fn enable_encryption(&mut self, passphrase: &str) -> Result<()> {
+        self.unlocked()?;
+
+        if self.is_encrypted()? {
+            bail!("Database is already encrypted")
+        }
+
+        if !self.store.encrypted() {
+            bail!("Tasks of this database can't be encrypted")
+        }
+
+        self.recrypt(Some(Cipher::create(passphrase)?))
+    }
+
+    pub fn disable_encryption(&mut self) -> Result<()> {
+        self.unlocked()?;
+
+        if !self.is_encrypted()? {
+            bail!("Database is not encrypted")
+        }
+
+        self.recrypt(None)
+    }
+
+    /// Re-encrypts the database with a key derived from a new passphrase.
+    pub fn change_passphrase(&mut self, passphrase: &str) -> Result<()> {
+        self.unlocked()?;
+
+        if !self.is_encrypted()? {
+            bail!("Database is not encrypted")
+        }
+
+        self.recrypt(Some(Cipher::create(passphrase)?))
+    }
+
+    pub(crate) fn unlocked(&self) -> Result<()> {
+        if self.locked {
+            bail!("Database is locked")
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn unseal(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.unlocked()?;
+        self.codec.unseal(data)
+    }
+
+    /// Trees whose values go through [`Database::encode`].
+    fn sealed_trees(&self) -> Vec<&Tree> {
+        vec![&self.tasks, &self.account, &self.undo, &self.redo, &self.history, &self.projects]
+    }
+
+    fn header(&self) -> Result<Option<Header>> {
+        match self.meta.get(ENCRYPTION_KEY)? {
+            Some(data) => Ok(Some(
+                bincode::serde::decode_from_slice(&data, self.config)?.0,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Rewrites every sealed value and the encryption header in one
+    /// transaction, so an interrupted switch leaves the old key in place.
+    fn recrypt(&mut self, next: Option<(Cipher, Header)>) -> Result<()> {
+        let header = next
+            .as_ref()
+            .map(|(_, header)| bincode::serde::encode_to_vec(header, self.config))
+            .transpose()?;
+        let next = next.map(|(cipher, _)| cipher);
+
+        let mut trees = self.sealed_trees();
+        let mut values = Vec::new();
+
+        for (i, tree) in trees.iter().enumerate() {
+            for record in tree.iter() {
+                let (key, value) = record?;
+                let value = self.unseal(&value)?;
+                let value = match &next {
+                    Some(cipher) => cipher.encrypt(&value)?,
+                    None => value,
+                };
+
+                values.push((i, key, value));
+            }
+        }
+
+        trees.push(&self.meta);
+
+        trees
+            .as_slice()
+            .transaction(|trees| {
+                for (i, key, value) in &values {
+                    trees[*i].insert(key, value.as_slice())?;
+                }
+
+                let meta = trees.last().unwrap();
+
+                match &header {
+                    Some(header) => meta.insert(ENCRYPTION_KEY, header.as_slice())?,
+                    None => meta.remove(ENCRYPTION_KEY)?,
+                };
+
+                Ok(())
+            })
+            .map_err(|e: TransactionError| anyhow!(e))?;
+
+        self.codec.set_cipher(next);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Task;
+
+    fn encrypted() -> (sled::Db, Database) {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let mut database = Database::load(db.clone()).unwrap();
+
+        database
+            .create_task(&Task {
+                title: "Secret".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        database.enable_encryption("hunter2").unwrap();
+
+        (db, database)
+    }
+
+    #[test]
+    fn values_are_encrypted() {
+        let (_, database) = encrypted();
+
+        let (_, data) = database.tasks.iter().next().unwrap().unwrap();
+
+        assert!(!data.windows(6).any(|w| w == b"Secret"));
+        assert_eq!(database.list_tasks().unwrap()[0].1.title, "Secret");
+    }
+
+    #[test]
+    fn unlock_checks_passphrase() {
+        let (db, _) = encrypted();
+        let mut database = Database::load(db).unwrap();
+
+        assert!(database.is_locked());
+        assert!(database.list_tasks().is_err());
+
+        let err = database.unlock("hunter3").unwrap_err();
+        assert!(err.is::<WrongPassphrase>());
+        assert!(database.is_locked());
+
+        database.unlock("hunter2").unwrap();
+        assert_eq!(database.list_tasks().unwrap()[0].1.title, "Secret");
+    }
+
+    #[test]
+    fn change_and_disable() {
+        let (db, mut database) = encrypted();
+
+        database.change_passphrase("correct horse").unwrap();
+        drop(database);
+
+        let mut database = Database::load(db.clone()).unwrap();
+        assert!(database.unlock("hunter2").is_err());
+        database.unlock("correct horse").unwrap();
+        database.disable_encryption().unwrap();
+        drop(database);
+
+        let database = Database::load(db).unwrap();
+        assert!(!database.is_locked());
+        assert_eq!(database.list_tasks().unwrap()[0].1.title, "Secret");
+        assert_eq!(database.account().unwrap().exp, 0);
+    }
+}
@@ -0,0 +1,301 @@
+//! Deadlines typed by hand, like `tomorrow 9am`, `next fri`, `in 3 days`,
+//! `2026-11-02 18:00` or `eod`.
+//!
+//! A date without a time means the end of that day, a time without a date
+//! the next time the clock shows it. `this <weekday>` is the day of the
+//! current week, `next <weekday>` the day of the following one and a bare
+//! weekday the first one after today.
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+
+const FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"];
+
+/// Reads a deadline relative to `now`, see the module documentation.
+pub fn parse_deadline(input: &str, now: NaiveDateTime) -> Result<NaiveDateTime> {
+    let input = input.trim().to_lowercase();
+
+    if input.is_empty() {
+        bail!("Empty date")
+    }
+
+    if let Some(date) = FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&input, format).ok())
+    {
+        return Ok(date);
+    }
+
+    let today = now.date();
+    let mut date = None;
+    let mut time = None;
+    let mut words = input.split_whitespace().filter(|w| *w != "at").peekable();
+
+    while let Some(word) = words.next() {
+        let day = match word {
+            "today" => Some(today),
+            "tomorrow" | "tmr" => Some(today + Duration::days(1)),
+            "eod" => {
+                set(&mut time, end_of_day(), word)?;
+                Some(today)
+            }
+            "eow" => {
+                set(&mut time, end_of_day(), word)?;
+                Some(week_start(today) + Duration::days(6))
+            }
+            "noon" => {
+                set(&mut time, NaiveTime::from_hms_opt(12, 0, 0).unwrap(), word)?;
+                None
+            }
+            "next" | "this" => {
+                let unit = words
+                    .next()
+                    .with_context(|| format!("Expected a weekday, week or month after {}", word))?;
+                let week = match word {
+                    "next" => week_start(today) + Duration::days(7),
+                    _ => week_start(today),
+                };
+
+                Some(match unit {
+                    "week" => week,
+                    "month" => {
+                        let month = today.with_day(1).unwrap();
+
+                        match word {
+                            "next" => month + Months::new(1),
+                            _ => month,
+                        }
+                    }
+                    unit => {
+                        let weekday = parse_weekday(unit).with_context(|| {
+                            format!("Expected a weekday after {}: {}", word, unit)
+                        })?;
+
+                        week + Duration::days(weekday.num_days_from_monday().into())
+                    }
+                })
+            }
+            "in" => {
+                let (count, unit) = match words.next() {
+                    Some(word) if word.chars().all(|c| c.is_ascii_digit()) => (
+                        word.to_string(),
+                        words.next().unwrap_or_default().to_string(),
+                    ),
+                    Some(word) => {
+                        let split = word
+                            .find(|c: char| !c.is_ascii_digit())
+                            .unwrap_or(word.len());
+
+                        (word[..split].to_string(), word[split..].to_string())
+                    }
+                    None => bail!("Expected an amount after in, like in 3 days"),
+                };
+                let count = count
+                    .parse::<u32>()
+                    .with_context(|| format!("Expected an amount after in: {}", count))?;
+
+                let too_far = || format!("Too far in the future: in {} {}", count, unit);
+
+                match unit.trim_end_matches('s') {
+                    "min" | "minute" | "m" => {
+                        return after(now, Duration::minutes(count.into()), words)
+                    }
+                    "hour" | "h" => return after(now, Duration::hours(count.into()), words),
+                    "day" | "d" => Some(
+                        today
+                            .checked_add_signed(Duration::days(count.into()))
+                            .with_context(too_far)?,
+                    ),
+                    "week" | "w" => Some(
+                        today
+                            .checked_add_signed(Duration::weeks(count.into()))
+                            .with_context(too_far)?,
+                    ),
+                    "month" => Some(
+                        today
+                            .checked_add_months(Months::new(count))
+                            .with_context(too_far)?,
+                    ),
+                    "" => bail!("Expected a unit after in {}, like days", count),
+                    unit => bail!("Unknown unit: {}", unit),
+                }
+            }
+            word => {
+                if let Some(weekday) = parse_weekday(word) {
+                    let days = (7 + weekday.num_days_from_monday()
+                        - today.weekday().num_days_from_monday())
+                        % 7;
+
+                    Some(today + Duration::days(if days == 0 { 7 } else { days.into() }))
+                } else if let Ok(day) = NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+                    Some(day)
+                } else {
+                    let word = match words.next_if(|w| *w == "am" || *w == "pm") {
+                        Some(suffix) => format!("{}{}", word, suffix),
+                        None => word.to_string(),
+                    };
+
+                    set(&mut time, parse_time(&word)?, &word)?;
+                    None
+                }
+            }
+        };
+
+        if let Some(day) = day {
+            set(&mut date, day, word)?;
+        }
+    }
+
+    Ok(match (date, time) {
+        (Some(date), time) => date.and_time(time.unwrap_or_else(end_of_day)),
+        (None, Some(time)) if today.and_time(time) > now => today.and_time(time),
+        (None, Some(time)) => (today + Duration::days(1)).and_time(time),
+        (None, None) => unreachable!("every word sets a date or a time"),
+    })
+}
+
+/// `now` plus `duration`, nothing being allowed after it.
+fn after<'a>(
+    now: NaiveDateTime,
+    duration: Duration,
+    mut words: impl Iterator<Item = &'a str>,
+) -> Result<NaiveDateTime> {
+    match words.next() {
+        Some(word) => bail!("Unexpected {} after a number of hours or minutes", word),
+        None => now
+            .checked_add_signed(duration)
+            .with_context(|| "Too far in the future"),
+    }
+}
+
+fn set<T>(value: &mut Option<T>, new: T, word: &str) -> Result<()> {
+    if value.is_some() {
+        bail!("Given twice: {}", word)
+    }
+
+    *value = Some(new);
+
+    Ok(())
+}
+
+fn end_of_day() -> NaiveTime {
+    NaiveTime::from_hms_opt(23, 59, 59).unwrap()
+}
+
+fn week_start(date: NaiveDate) -> NaiveDate {
+    date - Duration::days(date.weekday().num_days_from_monday().into())
+}
+
+fn parse_weekday(word: &str) -> Option<Weekday> {
+    match word.len() {
+        3.. => word.parse().ok(),
+        _ => None,
+    }
+}
+
+/// `9am`, `9:30pm`, `21:00` or `21:00:30`.
+fn parse_time(word: &str) -> Result<NaiveTime> {
+    let invalid = || format!("Unknown word: {}", word);
+
+    let (clock, offset) = match word.strip_suffix("am").or_else(|| word.strip_suffix("pm")) {
+        Some(clock) => (clock, Some(word.ends_with("pm"))),
+        None => (word, None),
+    };
+    let parts = clock
+        .split(':')
+        .map(|part| part.parse::<u32>().ok().filter(|_| !part.is_empty()))
+        .collect::<Option<Vec<_>>>()
+        .with_context(invalid)?;
+
+    let (hour, minute, second) = match (parts.as_slice(), offset) {
+        ([hour], Some(_)) => (*hour, 0, 0),
+        ([hour, minute], _) => (*hour, *minute, 0),
+        ([hour, minute, second], None) => (*hour, *minute, *second),
+        _ => bail!(invalid()),
+    };
+
+    let hour = match offset {
+        Some(_) if !(1..=12).contains(&hour) => bail!("Invalid time: {}", word),
+        Some(pm) => hour % 12 + if pm { 12 } else { 0 },
+        None => hour,
+    };
+
+    NaiveTime::from_hms_opt(hour, minute, second).with_context(|| format!("Invalid time: {}", word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Saturday 2026-10-17, 14:00.
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 17)
+            .unwrap()
+            .and_hms_opt(14, 0, 0)
+            .unwrap()
+    }
+
+    fn date(month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn end(month: u32, day: u32) -> NaiveDateTime {
+        date(month, day, 23, 59) + Duration::seconds(59)
+    }
+
+    #[test]
+    fn parses_deadlines() {
+        let cases = [
+            ("tomorrow 9am", date(10, 18, 9, 0)),
+            ("Tomorrow at 9:30 pm", date(10, 18, 21, 30)),
+            ("fri", end(10, 23)),
+            ("sat", end(10, 24)),
+            ("next fri", end(10, 23)),
+            ("next sun", end(10, 25)),
+            ("this mon 8:00", date(10, 12, 8, 0)),
+            ("next week", end(10, 19)),
+            ("next month", end(11, 1)),
+            ("in 3 days", end(10, 20)),
+            ("in 2w", end(10, 31)),
+            ("in 90 minutes", date(10, 17, 15, 30)),
+            ("2026-11-02", end(11, 2)),
+            ("2026-11-02 18:00", date(11, 2, 18, 0)),
+            ("2026-11-02 6pm", date(11, 2, 18, 0)),
+            ("eod", end(10, 17)),
+            ("eow", end(10, 18)),
+            ("noon", date(10, 18, 12, 0)),
+            ("15:00", date(10, 17, 15, 0)),
+            ("12am", date(10, 18, 0, 0)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_deadline(input, now()).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_nonsense() {
+        for input in [
+            "",
+            "soon",
+            "next",
+            "next fortnight",
+            "in days",
+            "in 3",
+            "in 3 parsecs",
+            "13pm",
+            "25:00",
+            "today tomorrow",
+            "in 2 hours 9am",
+            "in 99999999 days",
+            "in 99999999 weeks",
+            "in 4294967295 months",
+            "in 4294967295 hours",
+        ] {
+            assert!(parse_deadline(input, now()).is_err(), "{}", input);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Tasks that must be done before others.
+//!
+//! A task keeps the ids of the tasks it waits for in [`Task::depends_on`]
+//! and is blocked while any of them is neither done nor archived. Ids of
+//! deleted tasks are ignored. [`Database::add_dependency`] refuses links
+//! that would close a cycle, and walking the chains stops at tasks already
+//! seen should one come from an import.
+
+use crate::{Database, Task, TaskId};
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Returned by [`Database::set_done`] for a task waiting for others.
+#[derive(Debug)]
+pub struct Blocked {
+    pub id: TaskId,
+    /// Titles of the tasks not done yet.
+    pub blockers: Vec<String>,
+}
+
+impl Display for Blocked {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let blockers = self
+            .blockers
+            .iter()
+            .map(|title| format!("\"{}\"", title))
+            .collect::<Vec<_>>();
+
+        write!(f, "Task {} waits for {}", self.id, blockers.join(", "))
+    }
+}
+
+impl Error for Blocked {}
+
+/// A task in a dependency chain, `depth` 1 being a direct link.
+#[derive(Clone, Default)]
+pub struct ChainLink {
+    pub depth: usize,
+    pub id: TaskId,
+    pub task: Task,
+}
+
+impl Database {
+    /// Makes `id` wait for `on`. Fails if either task is missing or if `on`
+    /// already waits for `id`, directly or through other tasks.
+    pub fn add_dependency(&self, id: &str, on: &str) -> Result<()> {
+        let mut task = self.get_task(id)?.with_context(|| format!("Not found task {}", id))?;
+
+        if self.get_task(on)?.is_none() {
+            bail!("Not found task {}", on)
+        }
+
+        if id == on {
+            bail!("A task can't depend on itself")
+        }
+
+        if self.upstream(on)?.iter().any(|link| link.id == id) {
+            bail!("Task {} already waits for {}, that would be a cycle", on, id)
+        }
+
+        if task.depends_on.insert(on.to_string()) {
+            self.update_task(id, &task)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_dependency(&self, id: &str, on: &str) -> Result<()> {
+        let mut task = self.get_task(id)?.with_context(|| format!("Not found task {}", id))?;
+
+        if task.depends_on.remove(on) {
+            self.update_task(id, &task)?;
+        }
+
+        Ok(())
+    }
+
+    /// Tasks `id` waits for, directly or not, depth first.
+    pub fn upstream(&self, id: &str) -> Result<Vec<ChainLink>> {
+        let tasks = self.list_tasks()?.into_iter().collect::<HashMap<_, _>>();
+
+        Ok(chain(id, &tasks, |id| {
+            tasks
+                .get(id)
+                .map(|task| task.depends_on.iter().cloned().collect())
+                .unwrap_or_default()
+        }))
+    }
+
+    /// Tasks waiting for `id`, directly or not, depth first.
+    pub fn downstream(&self, id: &str) -> Result<Vec<ChainLink>> {
+        let tasks = self.list_tasks()?.into_iter().collect::<HashMap<_, _>>();
+        let mut dependents = HashMap::<&str, Vec<TaskId>>::new();
+
+        for (task_id, task) in &tasks {
+            for on in &task.depends_on {
+                dependents.entry(on).or_default().push(task_id.clone());
+            }
+        }
+
+        for ids in dependents.values_mut() {
+            ids.sort();
+        }
+
+        Ok(chain(id, &tasks, |id| dependents.get(id).cloned().unwrap_or_default()))
+    }
+
+    /// Direct dependencies of `id` that are neither done nor archived.
+    pub fn blockers(&self, id: &str) -> Result<Vec<(TaskId, Task)>> {
+        let task = self.get_task(id)?.with_context(|| format!("Not found task {}", id))?;
+
+        let mut blockers = Vec::new();
+
+        for on in &task.depends_on {
+            if let Some(dependency) = self.get_task(on)? {
+                if !resolved(&dependency) {
+                    blockers.push((on.clone(), dependency));
+                }
+            }
+        }
+
+        Ok(blockers)
+    }
+
+    /// Ids of every task with a dependency that is neither done nor archived.
+    pub fn blocked_tasks(&self) -> Result<HashSet<TaskId>> {
+        let tasks = self.list_tasks()?.into_iter().collect::<HashMap<_, _>>();
+
+        Ok(tasks
+            .iter()
+            .filter(|(_, task)| {
+                task.depends_on
+                    .iter()
+                    .any(|on| tasks.get(on).is_some_and(|dependency| !resolved(dependency)))
+            })
+            .map(|(id, _)| id.clone())
+            .collect())
+    }
+
+    /// Fails with [`Blocked`] if `id` has dependencies not done yet.
+    pub(crate) fn check_blocked(&self, id: &str) -> Result<()> {
+        let blockers = self.blockers(id)?;
+
+        if !blockers.is_empty() {
+            return Err(Blocked {
+                id: id.to_string(),
+                blockers: blockers.into_iter().map(|(_, task)| task.title).collect(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+fn resolved(task: &Task) -> bool {
+    task.done || task.archived.is_some()
+}
+
+fn chain<F>(id: &str, tasks: &HashMap<TaskId, Task>, links: F) -> Vec<ChainLink>
+where
+    F: Fn(&str) -> Vec<TaskId>,
+{
+    let mut seen = HashSet::from([id.to_string()]);
+    let mut stack = links(id).into_iter().rev().map(|id| (1, id)).collect::<Vec<_>>();
+    let mut chain = Vec::new();
+
+    while let Some((depth, id)) = stack.pop() {
+        let task = match tasks.get(&id) {
+            Some(task) if seen.insert(id.clone()) => task,
+            _ => continue,
+        };
+
+        stack.extend(links(&id).into_iter().rev().map(|id| (depth + 1, id)));
+        chain.push(ChainLink {
+            depth,
+            id,
+            task: task.clone(),
+        });
+    }
+
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn task(title: &str) -> Task {
+        Task {
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chains_and_cycles() {
+        let database = Database::temporary().unwrap();
+        let buy = database.create_task(&task("Buy paint")).unwrap();
+        let paint = database.create_task(&task("Paint")).unwrap();
+        let hang = database.create_task(&task("Hang pictures")).unwrap();
+
+        database.add_dependency(&paint, &buy).unwrap();
+        database.add_dependency(&hang, &paint).unwrap();
+
+        assert!(database.add_dependency(&buy, &hang).is_err());
+        assert!(database.add_dependency(&buy, &buy).is_err());
+        assert!(database.add_dependency(&buy, "missing").is_err());
+
+        let ids = |chain: Vec<ChainLink>| {
+            chain
+                .into_iter()
+                .map(|link| (link.depth, link.id))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(database.upstream(&hang).unwrap()),
+            vec![(1, paint.clone()), (2, buy.clone())]
+        );
+        assert_eq!(
+            ids(database.downstream(&buy).unwrap()),
+            vec![(1, paint.clone()), (2, hang.clone())]
+        );
+
+        database.remove_dependency(&hang, &paint).unwrap();
+        assert!(database.downstream(&paint).unwrap().is_empty());
+    }
+
+    #[test]
+    fn blocked_tasks_cant_be_done() {
+        let database = Database::temporary().unwrap();
+        let now = NaiveDateTime::default();
+        let buy = database.create_task(&task("Buy paint")).unwrap();
+        let paint = database.create_task(&task("Paint")).unwrap();
+
+        database.add_dependency(&paint, &buy).unwrap();
+        assert_eq!(database.blocked_tasks().unwrap(), HashSet::from([paint.clone()]));
+
+        let error = database.set_done(&paint, true, now).unwrap_err();
+        assert!(error.downcast_ref::<Blocked>().is_some());
+        assert!(!database.get_task(&paint).unwrap().unwrap().done);
+
+        database.set_done(&buy, true, now).unwrap();
+        assert!(database.blocked_tasks().unwrap().is_empty());
+        database.set_done(&paint, true, now).unwrap();
+
+        database.delete_task(&buy).unwrap();
+        assert!(database.blockers(&paint).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,538 @@
+//! Import and export of the whole database.
+//!
+//! The JSON format is a single object:
+//!
+//! ```json
+//! {
+//!   "version": 2,
+//!   "account": { "lvl": 1, "exp": 45, "achievements": ["FirstTask"] },
+//!   "projects": [
+//!     { "id": "Uakgb_J5m9g-0JDMbcJqL", "name": "Home", "creation_date": "2022-09-01T10:00:00" }
+//!   ],
+//!   "tasks": [
+//!     {
+//!       "id": "V1StGXR8_Z5jdHi6B-myT",
+//!       "title": "Water the plants",
+//!       "description": "",
+//!       "done": false,
+//!       "exp_added": false,
+//!       "creation_date": "2022-09-10T08:00:00",
+//!       "preferences": {
+//!         "repeat": { "Days": 1 },
+//!         "expire": "2022-09-11T20:00:00",
+//!         "exp": 30,
+//!         "auto_complete": false
+//!       },
+//!       "completions": [],
+//!       "archived": null,
+//!       "subtasks": [
+//!         { "title": "Balcony", "done": true, "exp": 5, "exp_added": true }
+//!       ],
+//!       "project": "Uakgb_J5m9g-0JDMbcJqL",
+//!       "tags": ["garden", "home"],
+//!       "priority": "High",
+//!       "depends_on": ["V1StGXR8_Z5jdHi6B-myU"]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Missing task fields take their default values. When merging, a project
+//! named like an existing one is replaced by it in the imported tasks.
+//!
+//! Version 1 files, written before checklists, projects, tags, priorities
+//! and dependencies, are read the same way: their tasks get the defaults
+//! and, having no `projects`, they leave the existing projects alone.
+//!
+//! The CSV format holds tasks only, one per row with the columns `id`,
+//! `title`, `description`, `done`, `exp_added`, `creation_date`, `expire`,
+//! `exp`, `repeat`, `archived`, `completions`, `auto_complete`, `subtasks`,
+//! `project`, `tags`, `priority` and `depends_on`:
+//!
+//! - dates are `YYYY-MM-DD HH:MM:SS` and `repeat` is an RRULE
+//! - `completions` are separated by `;`
+//! - `subtasks` is the checklist as JSON, empty without one
+//! - `project` is a project id
+//! - `tags` and the task ids of `depends_on` are separated by spaces
+//! - `priority` is `low`, `medium`, `high` or `urgent`, empty without one,
+//!   where JSON has `None`, `Low`, `Medium`, `High` or `Urgent`
+
+use crate::{
+    parse_tags, Account, Database, Preferences, Priority, Project, ProjectId, Recurrence, Task,
+    TaskId,
+};
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+
+const EXPORT_VERSION: u32 = 2;
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Keep existing tasks and the account, skipping tasks whose id already exists.
+    Merge,
+    /// Remove every task first; the account and projects are replaced too
+    /// if the file has them.
+    Replace,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Ids that already existed in the database and were skipped.
+    pub duplicates: Vec<TaskId>,
+    /// Existing tasks updated in place, for formats that carry their ids.
+    pub updated: usize,
+    /// Parts of the file left out without failing the import.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Export {
+    version: u32,
+    account: Option<Account>,
+    /// Missing from files written before projects existed.
+    #[serde(default)]
+    projects: Option<Vec<ExportProject>>,
+    tasks: Vec<ExportTask>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportProject {
+    id: ProjectId,
+    #[serde(flatten)]
+    project: Project,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportTask {
+    id: TaskId,
+    #[serde(flatten)]
+    task: Task,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CsvTask {
+    id: TaskId,
+    title: String,
+    description: String,
+    done: bool,
+    exp_added: bool,
+    creation_date: String,
+    expire: String,
+    exp: u32,
+    repeat: String,
+    archived: String,
+    completions: String,
+    #[serde(default)]
+    auto_complete: bool,
+    /// The checklist as JSON, empty without one.
+    #[serde(default)]
+    subtasks: String,
+    #[serde(default)]
+    project: String,
+    /// Tags separated by spaces.
+    #[serde(default)]
+    tags: String,
+    /// `low`, `medium`, `high` or `urgent`, empty without a priority.
+    #[serde(default)]
+    priority: String,
+    /// Ids of the tasks to be done first, separated by spaces.
+    #[serde(default)]
+    depends_on: String,
+}
+
+impl Database {
+    pub fn export_json<W: Write>(&self, writer: W) -> Result<()> {
+        let export = Export {
+            version: EXPORT_VERSION,
+            account: Some(self.account()?),
+            projects: Some(
+                self.projects()?
+                    .into_iter()
+                    .map(|(id, project)| ExportProject { id, project })
+                    .collect(),
+            ),
+            tasks: self
+                .list_tasks()?
+                .into_iter()
+                .map(|(id, task)| ExportTask { id, task })
+                .collect(),
+        };
+
+        serde_json::to_writer_pretty(writer, &export)?;
+
+        Ok(())
+    }
+
+    pub fn import_json<R: Read>(&self, reader: R, mode: ImportMode) -> Result<ImportReport> {
+        let export: Export = serde_json::from_reader(reader)?;
+
+        if export.version > EXPORT_VERSION {
+            bail!("Unsupported export version {}", export.version)
+        }
+
+        let mut tasks = export
+            .tasks
+            .into_iter()
+            .map(|t| (t.id, t.task))
+            .collect::<Vec<_>>();
+
+        check_duplicates(&tasks)?;
+
+        self.batch(|| {
+            if let Some(projects) = export.projects {
+                let renamed = self.import_projects(projects, mode)?;
+
+                for (_, task) in &mut tasks {
+                    if let Some(id) = task.project.as_ref().and_then(|id| renamed.get(id)) {
+                        task.project = Some(id.clone());
+                    }
+                }
+            }
+
+            self.import(tasks, export.account, mode)
+        })
+    }
+
+    pub fn export_csv<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+
+        for (id, task) in self.list_tasks()? {
+            writer.serialize(CsvTask::from_task(id, &task)?)?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn import_csv<R: Read>(&self, reader: R, mode: ImportMode) -> Result<ImportReport> {
+        let tasks = csv::Reader::from_reader(reader)
+            .deserialize::<CsvTask>()
+            .map(|row| row?.into_task())
+            .collect::<Result<Vec<_>>>()?;
+
+        self.import(tasks, None, mode)
+    }
+
+    pub(crate) fn import(
+        &self,
+        tasks: Vec<(TaskId, Task)>,
+        account: Option<Account>,
+        mode: ImportMode,
+    ) -> Result<ImportReport> {
+        check_duplicates(&tasks)?;
+
+        self.batch(|| {
+            if mode == ImportMode::Replace {
+                for (id, _) in self.list_tasks()? {
+                    self.delete_task(&id)?;
+                }
+
+                if let Some(account) = account {
+                    self.save_account(&account)?;
+                }
+            }
+
+            let mut report = ImportReport::default();
+
+            for (id, task) in tasks {
+                if self.get_task(&id)?.is_some() {
+                    report.duplicates.push(id);
+                } else {
+                    self.insert_task(&id, &task)?;
+                    report.imported += 1;
+                }
+            }
+
+            Ok(report)
+        })
+    }
+
+    /// Stores the projects of an export. Returns the ids of the projects
+    /// merged into an existing one of the same name, with the id to use.
+    fn import_projects(
+        &self,
+        projects: Vec<ExportProject>,
+        mode: ImportMode,
+    ) -> Result<HashMap<ProjectId, ProjectId>> {
+        if mode == ImportMode::Replace {
+            for (id, project) in self.projects()? {
+                self.projects.remove(&id)?;
+                self.track_project(&id, Some(project), None);
+            }
+        }
+
+        let mut renamed = HashMap::new();
+
+        for ExportProject { id, project } in projects {
+            if self.get_project(&id)?.is_some() {
+                continue;
+            }
+
+            match self.find_project(&project.name)? {
+                Some((existing, _)) => {
+                    renamed.insert(id, existing);
+                }
+                None => {
+                    self.projects.insert(&id, self.encode(&project)?)?;
+                    self.track_project(&id, None, Some(&project));
+                }
+            }
+        }
+
+        Ok(renamed)
+    }
+}
+
+fn check_duplicates(tasks: &[(TaskId, Task)]) -> Result<()> {
+    let mut ids = HashSet::new();
+
+    for (id, _) in tasks {
+        if !ids.insert(id) {
+            bail!("Duplicate task id {} in import", id)
+        }
+    }
+
+    Ok(())
+}
+
+impl CsvTask {
+    fn from_task(id: TaskId, task: &Task) -> Result<Self> {
+        Ok(Self {
+            id,
+            title: task.title.clone(),
+            description: task.description.clone(),
+            done: task.done,
+            exp_added: task.exp_added,
+            creation_date: task.creation_date.format(DATE_FORMAT).to_string(),
+            expire: task.preferences.expire.format(DATE_FORMAT).to_string(),
+            exp: task.preferences.exp,
+            repeat: task
+                .preferences
+                .repeat
+                .as_ref()
+                .map_or(String::new(), |r| r.to_string()),
+            archived: task
+                .archived
+                .map_or(String::new(), |d| d.format(DATE_FORMAT).to_string()),
+            completions: task
+                .completions
+                .iter()
+                .map(|d| d.format(DATE_FORMAT).to_string())
+                .collect::<Vec<_>>()
+                .join(";"),
+            auto_complete: task.preferences.auto_complete,
+            subtasks: if task.subtasks.is_empty() {
+                String::new()
+            } else {
+                serde_json::to_string(&task.subtasks)?
+            },
+            project: task.project.clone().unwrap_or_default(),
+            tags: task.tags.iter().cloned().collect::<Vec<_>>().join(" "),
+            priority: match task.priority {
+                Priority::None => String::new(),
+                priority => priority.to_string(),
+            },
+            depends_on: task.depends_on.iter().cloned().collect::<Vec<_>>().join(" "),
+        })
+    }
+
+    fn into_task(self) -> Result<(TaskId, Task)> {
+        let task = Task {
+            title: self.title,
+            description: self.description,
+            done: self.done,
+            exp_added: self.exp_added,
+            creation_date: parse_date(&self.creation_date)?,
+            preferences: Preferences {
+                repeat: match self.repeat.as_str() {
+                    "" => None,
+                    repeat => Some(repeat.parse::<Recurrence>()?),
+                },
+                expire: parse_date(&self.expire)?,
+                exp: self.exp,
+                auto_complete: self.auto_complete,
+            },
+            completions: self
+                .completions
+                .split(';')
+                .filter(|d| !d.is_empty())
+                .map(parse_date)
+                .collect::<Result<_>>()?,
+            archived: match self.archived.as_str() {
+                "" => None,
+                date => Some(parse_date(date)?),
+            },
+            subtasks: match self.subtasks.as_str() {
+                "" => Vec::new(),
+                subtasks => serde_json::from_str(subtasks)?,
+            },
+            project: (!self.project.is_empty()).then_some(self.project),
+            tags: parse_tags(&self.tags)?,
+            priority: self.priority.parse()?,
+            depends_on: self.depends_on.split_whitespace().map(str::to_string).collect(),
+        };
+
+        Ok((self.id, task))
+    }
+}
+
+fn parse_date(date: &str) -> Result<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(date, DATE_FORMAT).with_context(|| format!("Invalid date: {}", date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Subtask;
+
+    fn database() -> Database {
+        let database = Database::temporary().unwrap();
+
+        database
+            .create_task(&Task {
+                title: "Water, the plants".to_string(),
+                description: "Balcony\n\"first\"".to_string(),
+                preferences: Preferences {
+                    repeat: Some(Recurrence::Days(2)),
+                    ..Default::default()
+                },
+                subtasks: vec![Subtask {
+                    title: "Balcony; then kitchen".to_string(),
+                    exp: 5,
+                    ..Default::default()
+                }],
+                tags: parse_tags("home garden").unwrap(),
+                priority: Priority::High,
+                ..Default::default()
+            })
+            .unwrap();
+        database.add_exp(120).unwrap();
+
+        database
+    }
+
+    fn round_trip(
+        export: fn(&Database, &mut Vec<u8>) -> Result<()>,
+        import: fn(&Database, &[u8], ImportMode) -> Result<ImportReport>,
+    ) {
+        let source = database();
+        let mut data = Vec::new();
+        export(&source, &mut data).unwrap();
+
+        let target = Database::temporary().unwrap();
+        let report = import(&target, &data, ImportMode::Merge).unwrap();
+        assert_eq!(report.imported, 1);
+
+        let (id, task) = &target.list_tasks().unwrap()[0];
+        assert_eq!(id, &source.list_tasks().unwrap()[0].0);
+        assert_eq!(task.title, "Water, the plants");
+        assert_eq!(task.description, "Balcony\n\"first\"");
+        assert_eq!(task.preferences.repeat, Some(Recurrence::Days(2)));
+        assert_eq!(task.subtasks[0].title, "Balcony; then kitchen");
+        assert_eq!(task.subtasks[0].exp, 5);
+        assert_eq!(task.tags, parse_tags("garden home").unwrap());
+        assert_eq!(task.priority, Priority::High);
+
+        let report = import(&target, &data, ImportMode::Merge).unwrap();
+        assert_eq!(report.imported, 0);
+        assert_eq!(report.duplicates, vec![id.clone()]);
+
+        let report = import(&target, &data, ImportMode::Replace).unwrap();
+        assert_eq!(report.imported, 1);
+        assert_eq!(target.list_tasks().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        round_trip(
+            |db, w| db.export_json(w),
+            |db, r, mode| db.import_json(r, mode),
+        );
+
+        let mut data = Vec::new();
+        database().export_json(&mut data).unwrap();
+
+        let target = Database::temporary().unwrap();
+        target.import_json(data.as_slice(), ImportMode::Replace).unwrap();
+        assert_eq!(target.account().unwrap().exp, 120);
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        round_trip(
+            |db, w| db.export_csv(w),
+            |db, r, mode| db.import_csv(r, mode),
+        );
+    }
+
+    #[test]
+    fn json_keeps_projects() {
+        let source = Database::temporary().unwrap();
+        let now = NaiveDateTime::default();
+        let home = source.create_project("Home", now).unwrap();
+        source
+            .create_task(&Task {
+                project: Some(home.clone()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let mut data = Vec::new();
+        source.export_json(&mut data).unwrap();
+
+        let target = Database::temporary().unwrap();
+        target.import_json(data.as_slice(), ImportMode::Merge).unwrap();
+        assert_eq!(target.get_project(&home).unwrap().unwrap().name, "Home");
+        assert_eq!(target.list_tasks().unwrap()[0].1.project, Some(home));
+
+        let target = Database::temporary().unwrap();
+        let existing = target.create_project("home", now).unwrap();
+        target.import_json(data.as_slice(), ImportMode::Merge).unwrap();
+        assert_eq!(target.projects().unwrap().len(), 1);
+        assert_eq!(target.list_tasks().unwrap()[0].1.project, Some(existing.clone()));
+
+        target.import_json(data.as_slice(), ImportMode::Replace).unwrap();
+        assert_eq!(target.projects().unwrap()[0].1.name, "Home");
+
+        assert!(target.undo().unwrap());
+        assert_eq!(target.projects().unwrap()[0].1.name, "home");
+        assert_eq!(target.list_tasks().unwrap()[0].1.project, Some(existing));
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let data = r#"{"version": 1, "account": null, "tasks": [
+            {"id": "a", "title": "one", "preferences": {"exp": 30}}
+        ]}"#;
+
+        let database = Database::temporary().unwrap();
+        let home = database.create_project("Home", NaiveDateTime::default()).unwrap();
+        database.import_json(data.as_bytes(), ImportMode::Replace).unwrap();
+
+        let (_, task) = &database.list_tasks().unwrap()[0];
+        assert_eq!(task.preferences.exp, 30);
+        assert_eq!(task.priority, Priority::None);
+        assert!(task.depends_on.is_empty());
+        assert!(database.get_project(&home).unwrap().is_some());
+    }
+
+    #[test]
+    fn rejects_duplicates_in_file() {
+        let data = r#"{"version": 1, "account": null, "tasks": [
+            {"id": "a", "title": "one"},
+            {"id": "a", "title": "two"}
+        ]}"#;
+
+        let database = Database::temporary().unwrap();
+
+        assert!(database.import_json(data.as_bytes(), ImportMode::Merge).is_err());
+        assert!(database.list_tasks().unwrap().is_empty());
+    }
+}
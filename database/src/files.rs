@@ -0,0 +1,320 @@
+//! Tasks as plain-text files, one `<id>.md` per task:
+//!
+//! ```text
+//! +++
+//! title = "Water the plants"
+//! done = false
+//! exp_added = false
+//! creation_date = "2022-09-10T08:00:00"
+//! completions = []
+//!
+//! [preferences]
+//! expire = "2022-09-11T20:00:00"
+//! exp = 30
+//! +++
+//!
+//! Balcony first, then the kitchen.
+//! ```
+//!
+//! The TOML front matter holds every field except the description, which is
+//! the Markdown body. Missing fields take their default values.
+
+use crate::{Task, TaskId, TaskStore};
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const EXTENSION: &str = "md";
+const DELIMITER: &str = "+++";
+
+/// Used for commits when git has no user configured.
+const GIT_IDENTITY: [&str; 4] = ["-c", "user.name=tors", "-c", "user.email=tors@localhost"];
+
+pub struct FileStore {
+    dir: PathBuf,
+    git: bool,
+    identity: bool,
+}
+
+impl FileStore {
+    /// Creates `dir` if needed. With `git`, the directory is made a git
+    /// repository and every change is committed.
+    pub fn open<P: AsRef<Path>>(dir: P, git: bool) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed create task directory {}", dir.display()))?;
+
+        let mut store = Self {
+            dir,
+            git,
+            identity: true,
+        };
+
+        if git {
+            if !store.dir.join(".git").exists() {
+                store.run_git(&["init", "-q"])?;
+            }
+
+            store.identity = store.run_git(&["config", "user.email"]).is_ok();
+        }
+
+        Ok(store)
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf> {
+        if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+            bail!("Invalid task id {}", id)
+        }
+
+        Ok(self.dir.join(format!("{}.{}", id, EXTENSION)))
+    }
+
+    /// Commits the file of a task if it changed.
+    fn commit(&self, id: &str, message: &str) -> Result<()> {
+        if !self.git {
+            return Ok(());
+        }
+
+        let file = format!("{}.{}", id, EXTENSION);
+
+        self.run_git(&["add", "-A", "--", &file])?;
+
+        if self.run_git(&["diff", "--cached", "--quiet", "--", &file]).is_ok() {
+            return Ok(());
+        }
+
+        let mut args = if self.identity { vec![] } else { GIT_IDENTITY.to_vec() };
+        args.extend(["commit", "-q", "-m", message, "--", &file]);
+
+        self.run_git(&args)
+    }
+
+    fn run_git(&self, args: &[&str]) -> Result<()> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            .args(args)
+            .output()
+            .with_context(|| "Failed run git")?;
+
+        if !output.status.success() {
+            bail!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+
+        Ok(())
+    }
+}
+
+impl TaskStore for FileStore {
+    fn get(&self, id: &str) -> Result<Option<Task>> {
+        let path = self.path(id)?;
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path)?;
+
+        Ok(Some(
+            parse(&content).with_context(|| format!("Failed decode task {}", id))?,
+        ))
+    }
+
+    fn insert(&self, id: &str, task: &Task) -> Result<()> {
+        let path = self.path(id)?;
+        let verb = if path.exists() { "Update" } else { "Add" };
+
+        fs::write(&path, render(task)?)?;
+
+        self.commit(id, &format!("{} task {}: {}", verb, id, task.title))
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        let path = self.path(id)?;
+
+        if !path.exists() {
+            return Ok(());
+        }
+
+        fs::remove_file(&path)?;
+
+        self.commit(id, &format!("Remove task {}", id))
+    }
+
+    fn list(&self) -> Result<Vec<(TaskId, Task)>> {
+        let mut tasks = Vec::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+
+            if path.extension().is_none_or(|e| e != EXTENSION) {
+                continue;
+            }
+
+            let id = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(id) if !id.starts_with('.') => id.to_string(),
+                _ => continue,
+            };
+            let task = parse(&fs::read_to_string(&path)?)
+                .with_context(|| format!("Failed decode task {}", id))?;
+
+            tasks.push((id, task));
+        }
+
+        Ok(tasks)
+    }
+}
+
+fn render(task: &Task) -> Result<String> {
+    let mut table = toml::Table::try_from(task)?;
+    table.remove("description");
+
+    let mut content = format!("{}\n{}{}\n", DELIMITER, toml::to_string(&table)?, DELIMITER);
+
+    if !task.description.is_empty() {
+        content.push('\n');
+        content.push_str(&task.description);
+        content.push('\n');
+    }
+
+    Ok(content)
+}
+
+fn parse(content: &str) -> Result<Task> {
+    let content = content.replace("\r\n", "\n");
+
+    let rest = match content.strip_prefix(DELIMITER) {
+        Some(rest) if rest.starts_with('\n') => rest,
+        _ => bail!("Missing front matter"),
+    };
+
+    let (front, body) = match rest.find(&format!("\n{}", DELIMITER)) {
+        Some(i) => (&rest[..i], &rest[i + 1 + DELIMITER.len()..]),
+        None => bail!("Unterminated front matter"),
+    };
+
+    let mut table = front.parse::<toml::Table>()?;
+    // Only the end of the delimiter line, the blank line and the final line
+    // break that `render` adds, so that the description stays as it was
+    let body = body.strip_prefix('\n').unwrap_or(body);
+    let body = body.strip_prefix('\n').unwrap_or(body);
+    let description = body.strip_suffix('\n').unwrap_or(body);
+
+    table.insert("description".to_string(), description.to_string().into());
+
+    Ok(table.try_into()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Database, Preferences, Recurrence, Subtask};
+    use chrono::NaiveDate;
+
+    fn task() -> Task {
+        let date = NaiveDate::from_ymd_opt(2022, 9, 10).unwrap();
+
+        Task {
+            title: "Water the plants".to_string(),
+            description: "Balcony first\n\n+++ then the kitchen".to_string(),
+            creation_date: date.and_hms_opt(8, 0, 0).unwrap(),
+            preferences: Preferences {
+                repeat: Some(Recurrence::Days(2)),
+                expire: date.and_hms_opt(20, 0, 0).unwrap(),
+                exp: 30,
+                auto_complete: true,
+            },
+            completions: vec![date.and_hms_opt(9, 0, 0).unwrap()],
+            subtasks: vec![Subtask {
+                title: "Kitchen".to_string(),
+                done: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn git_log(dir: &Path) -> Vec<String> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["log", "--format=%s"])
+            .output()
+            .unwrap();
+
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let content = render(&task()).unwrap();
+        let parsed = parse(&content).unwrap();
+
+        assert!(content.starts_with("+++\ntitle = \"Water the plants\"\n"));
+        assert!(content.ends_with("+++\n\nBalcony first\n\n+++ then the kitchen\n"));
+        assert_eq!(render(&parsed).unwrap(), content);
+
+        let parsed = parse("+++\ntitle = \"Call\"\n+++\n").unwrap();
+        assert_eq!(parsed.title, "Call");
+        assert_eq!(parsed.description, "");
+
+        for description in ["\nIndented\n", "\n", "Trailing\n\n"] {
+            let task = Task {
+                description: description.to_string(),
+                ..Default::default()
+            };
+
+            assert_eq!(parse(&render(&task).unwrap()).unwrap().description, description);
+        }
+    }
+
+    #[test]
+    fn database_uses_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut database = Database::temporary()
+            .unwrap()
+            .with_task_store(FileStore::open(dir.path(), false).unwrap());
+
+        let id = database.create_task(&task()).unwrap();
+
+        assert!(dir.path().join(format!("{}.md", id)).exists());
+        assert_eq!(database.list_tasks().unwrap()[0].1.title, "Water the plants");
+        assert!(database.enable_encryption("hunter2").is_err());
+
+        database.delete_task(&id).unwrap();
+        assert!(database.list_tasks().unwrap().is_empty());
+    }
+
+    #[test]
+    fn commits_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStore::open(dir.path(), true).unwrap();
+
+        store.insert("a", &task()).unwrap();
+        store.insert("a", &task()).unwrap();
+
+        let mut changed = task();
+        changed.done = true;
+        store.insert("a", &changed).unwrap();
+        store.remove("a").unwrap();
+
+        assert_eq!(
+            git_log(dir.path()),
+            vec![
+                "Remove task a",
+                "Update task a: Water the plants",
+                "Add task a: Water the plants",
+            ]
+        );
+    }
+}
@@ -0,0 +1,440 @@
+//! Timestamped history of every change to a task.
+//!
+//! Entries are kept in the `history` tree under the task id and a sled
+//! generated id, so the entries of a task come in the order they were
+//! written. They stay after the task is deleted and, like the rest of the
+//! database, are encrypted with it. Entries are JSON so that ones written
+//! before a new event was added still decode.
+
+use crate::{Database, ProjectId, Task};
+use anyhow::{Context, Result};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+
+pub(crate) const HISTORY_TREE: &str = "history";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Event {
+    Created { title: String },
+    Deleted,
+    Title { before: String, after: String },
+    Description { before: String, after: String },
+    Expire { before: NaiveDateTime, after: NaiveDateTime },
+    Exp { before: u32, after: u32 },
+    Repeat { before: Option<String>, after: Option<String> },
+    Done(bool),
+    ExpAwarded(u32),
+    Archived,
+    Restored,
+    /// A repeating task moved to its next period.
+    RolledOver { expire: NaiveDateTime },
+    AutoComplete(bool),
+    /// Checklist items added, removed or renamed.
+    Checklist,
+    SubtaskDone { title: String, done: bool },
+    /// Moved between projects, by the names they had at the time.
+    Project { before: Option<String>, after: Option<String> },
+    Tags { before: Vec<String>, after: Vec<String> },
+    /// Priorities by name, which stay readable if [`crate::Priority`] changes.
+    Priority { before: String, after: String },
+    /// Tasks to be done first added or removed.
+    Dependencies,
+}
+
+impl Event {
+    /// Events that turn `before` into `after`, `None` being a missing task.
+    pub(crate) fn diff(before: Option<&Task>, after: Option<&Task>) -> Vec<Event> {
+        let (before, after) = match (before, after) {
+            (None, None) => return Vec::new(),
+            (None, Some(task)) => {
+                return vec![Event::Created {
+                    title: task.title.clone(),
+                }]
+            }
+            (Some(_), None) => return vec![Event::Deleted],
+            (Some(before), Some(after)) => (before, after),
+        };
+
+        let mut events = Vec::new();
+
+        if before.title != after.title {
+            events.push(Event::Title {
+                before: before.title.clone(),
+                after: after.title.clone(),
+            });
+        }
+
+        if before.description != after.description {
+            events.push(Event::Description {
+                before: before.description.clone(),
+                after: after.description.clone(),
+            });
+        }
+
+        if before.preferences.expire != after.preferences.expire {
+            events.push(Event::Expire {
+                before: before.preferences.expire,
+                after: after.preferences.expire,
+            });
+        }
+
+        if before.preferences.exp != after.preferences.exp {
+            events.push(Event::Exp {
+                before: before.preferences.exp,
+                after: after.preferences.exp,
+            });
+        }
+
+        if before.preferences.repeat != after.preferences.repeat {
+            events.push(Event::Repeat {
+                before: before.preferences.repeat.as_ref().map(|r| r.to_string()),
+                after: after.preferences.repeat.as_ref().map(|r| r.to_string()),
+            });
+        }
+
+        if before.preferences.auto_complete != after.preferences.auto_complete {
+            events.push(Event::AutoComplete(after.preferences.auto_complete));
+        }
+
+        let titles = |task: &Task| {
+            task.subtasks
+                .iter()
+                .map(|s| s.title.clone())
+                .collect::<Vec<_>>()
+        };
+
+        if titles(before) != titles(after) {
+            events.push(Event::Checklist);
+        } else {
+            for (before, after) in before.subtasks.iter().zip(&after.subtasks) {
+                if before.done != after.done {
+                    events.push(Event::SubtaskDone {
+                        title: after.title.clone(),
+                        done: after.done,
+                    });
+                }
+            }
+        }
+
+        if before.tags != after.tags {
+            events.push(Event::Tags {
+                before: before.tags.iter().cloned().collect(),
+                after: after.tags.iter().cloned().collect(),
+            });
+        }
+
+        if before.priority != after.priority {
+            events.push(Event::Priority {
+                before: before.priority.to_string(),
+                after: after.priority.to_string(),
+            });
+        }
+
+        if before.depends_on != after.depends_on {
+            events.push(Event::Dependencies);
+        }
+
+        if before.project != after.project {
+            events.push(Event::Project {
+                before: before.project.clone(),
+                after: after.project.clone(),
+            });
+        }
+
+        if before.done != after.done {
+            events.push(Event::Done(after.done));
+        }
+
+        match (before.archived, after.archived) {
+            (None, Some(_)) => events.push(Event::Archived),
+            (Some(_), None) => events.push(Event::Restored),
+            _ => {}
+        }
+
+        events
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let date = |date: &NaiveDateTime| date.format("%Y-%m-%d %H:%M:%S").to_string();
+        let repeat = |repeat: &Option<String>| repeat.clone().unwrap_or_else(|| "none".to_string());
+        let project = |name: &Option<String>| {
+            name.as_ref()
+                .map_or("none".to_string(), |name| format!("\"{}\"", name))
+        };
+        let tags = |tags: &[String]| match tags {
+            [] => "none".to_string(),
+            tags => tags.iter().map(|t| format!("#{}", t)).collect::<Vec<_>>().join(" "),
+        };
+
+        match self {
+            Event::Created { title } => write!(f, "created \"{}\"", title),
+            Event::Deleted => write!(f, "deleted"),
+            Event::Title { before, after } => write!(f, "title \"{}\" -> \"{}\"", before, after),
+            Event::Description { .. } => write!(f, "description edited"),
+            Event::Expire { before, after } => {
+                write!(f, "deadline {} -> {}", date(before), date(after))
+            }
+            Event::Exp { before, after } => write!(f, "exp {} -> {}", before, after),
+            Event::Repeat { before, after } => {
+                write!(f, "repeat {} -> {}", repeat(before), repeat(after))
+            }
+            Event::AutoComplete(true) => write!(f, "auto-complete on"),
+            Event::AutoComplete(false) => write!(f, "auto-complete off"),
+            Event::Checklist => write!(f, "checklist edited"),
+            Event::SubtaskDone { title, done: true } => write!(f, "checked \"{}\"", title),
+            Event::SubtaskDone { title, done: false } => write!(f, "unchecked \"{}\"", title),
+            Event::Project { before, after } => {
+                write!(f, "project {} -> {}", project(before), project(after))
+            }
+            Event::Tags { before, after } => {
+                write!(f, "tags {} -> {}", tags(before), tags(after))
+            }
+            Event::Priority { before, after } => write!(f, "priority {} -> {}", before, after),
+            Event::Dependencies => write!(f, "dependencies changed"),
+            Event::Done(true) => write!(f, "marked done"),
+            Event::Done(false) => write!(f, "marked not done"),
+            Event::ExpAwarded(exp) => write!(f, "awarded {} exp", exp),
+            Event::Archived => write!(f, "archived"),
+            Event::Restored => write!(f, "restored"),
+            Event::RolledOver { expire } => write!(f, "repeated, next deadline {}", date(expire)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub time: NaiveDateTime,
+    pub event: Event,
+}
+
+impl Database {
+    /// Changes of a task, oldest first, empty for a task without history.
+    pub fn task_history(&self, id: &str) -> Result<Vec<HistoryEntry>> {
+        self.history
+            .scan_prefix(prefix(id))
+            .values()
+            .map(|data| {
+                self.decode_entry(&data?)
+                    .with_context(|| format!("Failed decode history of task {}", id))
+            })
+            .collect()
+    }
+
+    /// Consecutive days, up to today or yesterday, on which some task was
+    /// marked done and not unmarked afterwards.
+    pub fn streak(&self, now: NaiveDateTime) -> Result<u32> {
+        let mut days = BTreeSet::new();
+        let mut pending = HashMap::<Vec<u8>, Vec<NaiveDate>>::new();
+
+        for record in self.history.iter() {
+            let (key, data) = record?;
+            let entry = self.decode_entry(&data).with_context(|| "Failed decode history")?;
+            let id = key.splitn(2, |&b| b == 0).next().unwrap_or_default().to_vec();
+            let done = pending.entry(id).or_default();
+
+            match entry.event {
+                Event::Done(true) => done.push(entry.time.date()),
+                Event::Done(false) => {
+                    done.pop();
+                }
+                Event::RolledOver { .. } => days.extend(done.drain(..)),
+                _ => {}
+            }
+        }
+
+        days.extend(pending.into_values().flatten());
+
+        let mut day = now.date();
+
+        if !days.contains(&day) {
+            day -= Duration::days(1);
+        }
+
+        let mut streak = 0;
+
+        while days.contains(&day) {
+            streak += 1;
+            day -= Duration::days(1);
+        }
+
+        Ok(streak)
+    }
+
+    pub(crate) fn record(&self, id: &str, time: NaiveDateTime, events: Vec<Event>) -> Result<()> {
+        for event in events {
+            let event = match event {
+                Event::Project { before, after } => Event::Project {
+                    before: self.project_name(before)?,
+                    after: self.project_name(after)?,
+                },
+                event => event,
+            };
+            let mut key = prefix(id);
+            key.extend(self.db.generate_id()?.to_be_bytes());
+
+            self.history
+                .insert(key, self.encode_entry(&HistoryEntry { time, event })?)?;
+        }
+
+        Ok(())
+    }
+
+    /// The name of a project, its id once deleted.
+    fn project_name(&self, id: Option<ProjectId>) -> Result<Option<String>> {
+        Ok(match id {
+            Some(id) => Some(self.get_project(&id)?.map_or(id, |project| project.name)),
+            None => None,
+        })
+    }
+
+    pub(crate) fn encode_entry(&self, entry: &HistoryEntry) -> Result<Vec<u8>> {
+        self.unlocked()?;
+        self.codec.seal(serde_json::to_vec(entry)?)
+    }
+
+    pub(crate) fn decode_entry(&self, data: &[u8]) -> Result<HistoryEntry> {
+        self.unlocked()?;
+        Ok(serde_json::from_slice(&self.codec.unseal(data)?)?)
+    }
+}
+
+fn prefix(id: &str) -> Vec<u8> {
+    let mut key = id.as_bytes().to_vec();
+    key.push(0);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn date(day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2022, 9, day)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    fn events(database: &Database, id: &str) -> Vec<Event> {
+        database
+            .task_history(id)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect()
+    }
+
+    #[test]
+    fn records_changes() {
+        let database = Database::temporary().unwrap();
+        let mut task = Task {
+            title: "Water".to_string(),
+            ..Default::default()
+        };
+        let id = database.create_task(&task).unwrap();
+
+        task.title = "Water plants".to_string();
+        task.preferences.exp = 30;
+        database.update_task(&id, &task).unwrap();
+        database.set_done(&id, true, date(10)).unwrap();
+        database.archive_task(&id, date(11)).unwrap();
+        database.delete_task(&id).unwrap();
+
+        assert_eq!(
+            events(&database, &id),
+            vec![
+                Event::Created {
+                    title: "Water".to_string()
+                },
+                Event::Title {
+                    before: "Water".to_string(),
+                    after: "Water plants".to_string()
+                },
+                Event::Exp {
+                    before: 25,
+                    after: 30
+                },
+                Event::Done(true),
+                Event::ExpAwarded(30),
+                Event::Archived,
+                Event::Deleted,
+            ]
+        );
+
+        let history = database.task_history(&id).unwrap();
+        assert!(database.task_history("missing").unwrap().is_empty());
+        assert_eq!(history[3].time, date(10));
+        assert_eq!(history[5].time, date(11));
+    }
+
+    #[test]
+    fn names_projects() {
+        let database = Database::temporary().unwrap();
+        let home = database.create_project("Home", date(1)).unwrap();
+        let work = database.create_project("Work", date(1)).unwrap();
+        let mut task = Task {
+            project: Some(home),
+            ..Default::default()
+        };
+        let id = database.create_task(&task).unwrap();
+
+        task.project = Some(work.clone());
+        database.update_task(&id, &task).unwrap();
+        database.rename_project(&work, "Office").unwrap();
+
+        let event = events(&database, &id).pop().unwrap();
+        assert_eq!(event.to_string(), "project \"Home\" -> \"Work\"");
+    }
+
+    /// Entries as written by earlier builds, which must keep decoding.
+    #[test]
+    fn reads_stored_entries() {
+        let database = Database::temporary().unwrap();
+        let event = |event: &str| {
+            let data = format!(r#"{{"time":"2022-09-10T12:00:00","event":{}}}"#, event);
+
+            database.decode_entry(data.as_bytes()).unwrap().event
+        };
+
+        assert_eq!(event(r#"{"Done":true}"#), Event::Done(true));
+        assert_eq!(event(r#""Checklist""#), Event::Checklist);
+        assert_eq!(
+            event(r#"{"SubtaskDone":{"title":"Soil","done":true}}"#),
+            Event::SubtaskDone {
+                title: "Soil".to_string(),
+                done: true
+            }
+        );
+        assert_eq!(
+            event(r#"{"Project":{"before":null,"after":"Home"}}"#),
+            Event::Project {
+                before: None,
+                after: Some("Home".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn counts_streak() {
+        let database = Database::temporary().unwrap();
+        let task = Task::default();
+        let a = database.create_task(&task).unwrap();
+        let b = database.create_task(&task).unwrap();
+        let c = database.create_task(&task).unwrap();
+
+        database.set_done(&a, true, date(8)).unwrap();
+        database.set_done(&b, true, date(9)).unwrap();
+        database.set_done(&c, true, date(10)).unwrap();
+        assert_eq!(database.streak(date(10)).unwrap(), 3);
+        assert_eq!(database.streak(date(11)).unwrap(), 3);
+        assert_eq!(database.streak(date(12)).unwrap(), 0);
+
+        database.set_done(&b, false, date(10)).unwrap();
+        assert_eq!(database.streak(date(10)).unwrap(), 1);
+    }
+}
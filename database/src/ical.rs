@@ -0,0 +1,556 @@
+//! iCalendar ([RFC 5545](https://www.rfc-editor.org/rfc/rfc5545)) VTODOs for tasks.
+//!
+//! The task id is the `UID`, so importing a file again updates tasks
+//! instead of adding them twice. Dates are written as floating local time;
+//! UTC dates are converted to local time on import and `TZID` is ignored.
+//! `PRIORITY` is 1 for urgent, 3 for high, 5 for medium and 9 for low
+//! tasks, read back by the ranges of RFC 5545. An `RRULE` tors can't repeat
+//! by is dropped on import, with a warning in the [`ImportReport`].
+
+use crate::{
+    parse_tag, Database, ImportMode, ImportReport, Priority, Recurrence, Task, TaskId, TaskState,
+};
+use anyhow::{bail, Context, Result};
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use std::io::{Read, Write};
+
+const DATE_TIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+const DATE_FORMAT: &str = "%Y%m%d";
+
+/// Longest line in octets before it's folded.
+const LINE_LIMIT: usize = 75;
+
+/// Experience of a task, not part of RFC 5545.
+const EXP_PROPERTY: &str = "X-TORS-EXP";
+
+/// A VTODO component.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Todo {
+    pub uid: TaskId,
+    pub summary: String,
+    pub description: String,
+    pub created: Option<NaiveDateTime>,
+    pub due: Option<NaiveDateTime>,
+    pub completed: Option<NaiveDateTime>,
+    pub done: bool,
+    pub rrule: Option<Recurrence>,
+    pub exp: Option<u32>,
+    pub categories: Vec<String>,
+    /// From 1, the highest, to 9; 0 or missing when undefined.
+    pub priority: Option<u8>,
+}
+
+impl Todo {
+    pub fn from_task(id: &str, task: &Task) -> Self {
+        Self {
+            uid: id.to_string(),
+            summary: task.title.clone(),
+            description: task.description.clone(),
+            created: Some(task.creation_date),
+            due: Some(task.preferences.expire),
+            completed: task.done.then(|| task.completions.last().copied()).flatten(),
+            done: task.done,
+            rrule: task.preferences.repeat.clone(),
+            exp: Some(task.preferences.exp),
+            categories: task.tags.iter().cloned().collect(),
+            priority: match task.priority {
+                Priority::None => None,
+                Priority::Low => Some(9),
+                Priority::Medium => Some(5),
+                Priority::High => Some(3),
+                Priority::Urgent => Some(1),
+            },
+        }
+    }
+
+    /// Applies the VTODO to `task`, keeping fields it doesn't set.
+    pub fn update(&self, task: &mut Task) {
+        task.title = self.summary.clone();
+        task.description = self.description.clone();
+        task.preferences.repeat = self.rrule.clone();
+        task.tags = self
+            .categories
+            .iter()
+            .filter_map(|category| parse_tag(&category.replace(' ', "-")).ok())
+            .collect();
+
+        if let Some(created) = self.created {
+            task.creation_date = created;
+        }
+
+        if let Some(due) = self.due {
+            task.preferences.expire = due;
+        }
+
+        if let Some(exp) = self.exp {
+            task.preferences.exp = exp;
+        }
+
+        if let Some(priority) = self.priority {
+            task.priority = match priority {
+                1 => Priority::Urgent,
+                2..=4 => Priority::High,
+                5 => Priority::Medium,
+                6..=9 => Priority::Low,
+                _ => Priority::None,
+            };
+        }
+
+        if self.done != task.done {
+            task.set_done(self.done, self.completed.unwrap_or(task.creation_date));
+        }
+    }
+
+    pub fn to_task(&self, now: NaiveDateTime) -> Task {
+        let mut task = Task {
+            creation_date: now,
+            ..Default::default()
+        };
+
+        self.update(&mut task);
+
+        task
+    }
+
+    /// Writes a VCALENDAR holding `todos`.
+    pub fn write_calendar(todos: &[Todo], now: NaiveDateTime) -> String {
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".to_string(),
+            "PRODID:-//reticulis//tors//EN".to_string(),
+        ];
+
+        let stamp = Local
+            .from_local_datetime(&now)
+            .earliest()
+            .map_or(now, |d| d.with_timezone(&Utc).naive_utc());
+
+        for todo in todos {
+            lines.push("BEGIN:VTODO".to_string());
+            lines.push(format!("UID:{}", escape(&todo.uid)));
+            lines.push(format!("DTSTAMP:{}Z", stamp.format(DATE_TIME_FORMAT)));
+            lines.push(format!("SUMMARY:{}", escape(&todo.summary)));
+
+            if !todo.description.is_empty() {
+                lines.push(format!("DESCRIPTION:{}", escape(&todo.description)));
+            }
+
+            if let Some(created) = todo.created {
+                lines.push(format!("CREATED:{}", created.format(DATE_TIME_FORMAT)));
+            }
+
+            if let Some(due) = todo.due {
+                lines.push(format!("DUE:{}", due.format(DATE_TIME_FORMAT)));
+            }
+
+            if todo.done {
+                lines.push("STATUS:COMPLETED".to_string());
+
+                if let Some(completed) = todo.completed {
+                    lines.push(format!("COMPLETED:{}", completed.format(DATE_TIME_FORMAT)));
+                }
+            } else {
+                lines.push("STATUS:NEEDS-ACTION".to_string());
+            }
+
+            if let Some(rrule) = &todo.rrule {
+                lines.push(format!("RRULE:{}", rrule));
+            }
+
+            if !todo.categories.is_empty() {
+                let categories = todo.categories.iter().map(|c| escape(c)).collect::<Vec<_>>();
+
+                lines.push(format!("CATEGORIES:{}", categories.join(",")));
+            }
+
+            if let Some(priority) = todo.priority {
+                lines.push(format!("PRIORITY:{}", priority));
+            }
+
+            if let Some(exp) = todo.exp {
+                lines.push(format!("{}:{}", EXP_PROPERTY, exp));
+            }
+
+            lines.push("END:VTODO".to_string());
+        }
+
+        lines.push("END:VCALENDAR".to_string());
+
+        lines.iter().map(|l| fold(l)).collect()
+    }
+
+    /// Reads every VTODO of a VCALENDAR, ignoring other components.
+    pub fn parse_calendar(data: &str) -> Result<Vec<Todo>> {
+        Self::parse(data, None)
+    }
+
+    /// Fails on an `RRULE` tors can't repeat by, unless given `warnings`
+    /// to drop the rule with a warning.
+    fn parse(data: &str, mut warnings: Option<&mut Vec<String>>) -> Result<Vec<Todo>> {
+        let mut todos = Vec::new();
+        let mut todo: Option<Todo> = None;
+        let mut dropped = None;
+        let mut depth = 0;
+
+        for line in unfold(data) {
+            let (name, value) = split_property(&line)?;
+
+            match (name.as_str(), todo.as_mut()) {
+                ("BEGIN", None) if value.eq_ignore_ascii_case("VTODO") => {
+                    todo = Some(Todo::default());
+                    dropped = None;
+                    depth = 0;
+                }
+                ("BEGIN", Some(_)) => depth += 1,
+                ("END", Some(_)) if depth > 0 => depth -= 1,
+                ("END", Some(_)) if value.eq_ignore_ascii_case("VTODO") => {
+                    let todo = todo.take().unwrap();
+
+                    if todo.uid.is_empty() {
+                        bail!("VTODO without UID")
+                    }
+
+                    if let (Some(dropped), Some(warnings)) =
+                        (dropped.take(), warnings.as_deref_mut())
+                    {
+                        warnings.push(format!("Task {}: {}", todo.uid, dropped));
+                    }
+
+                    todos.push(todo);
+                }
+                (_, Some(_)) if depth > 0 => {}
+                (_, Some(todo)) => match name.as_str() {
+                    "UID" => todo.uid = unescape(value),
+                    "SUMMARY" => todo.summary = unescape(value),
+                    "DESCRIPTION" => todo.description = unescape(value),
+                    "CREATED" => todo.created = Some(parse_date(value, false)?),
+                    "DUE" => todo.due = Some(parse_date(value, true)?),
+                    "COMPLETED" => {
+                        todo.completed = Some(parse_date(value, false)?);
+                        todo.done = true;
+                    }
+                    "STATUS" => todo.done = value.eq_ignore_ascii_case("COMPLETED"),
+                    "RRULE" => match value.parse() {
+                        Ok(rule) => todo.rrule = Some(rule),
+                        Err(err) if warnings.is_some() => {
+                            dropped = Some(format!("dropped RRULE {}: {:#}", value, err));
+                        }
+                        Err(err) => return Err(err),
+                    },
+                    "CATEGORIES" => todo.categories.extend(
+                        split_list(value)
+                            .into_iter()
+                            .map(|c| unescape(&c))
+                            .filter(|c| !c.is_empty()),
+                    ),
+                    "PRIORITY" => todo.priority = value.trim().parse().ok(),
+                    EXP_PROPERTY => todo.exp = value.parse().ok(),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        Ok(todos)
+    }
+}
+
+impl Database {
+    /// Writes every task that isn't archived as a VTODO.
+    pub fn export_ical<W: Write>(&self, mut writer: W, now: NaiveDateTime) -> Result<()> {
+        let todos = self
+            .list_tasks()?
+            .iter()
+            .filter(|(_, task)| task.state(now) != TaskState::Archived)
+            .map(|(id, task)| Todo::from_task(id, task))
+            .collect::<Vec<_>>();
+
+        writer.write_all(Todo::write_calendar(&todos, now).as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Adds tasks for new UIDs and updates tasks whose id is the UID.
+    pub fn import_ical<R: Read>(
+        &self,
+        mut reader: R,
+        mode: ImportMode,
+        now: NaiveDateTime,
+    ) -> Result<ImportReport> {
+        let mut data = String::new();
+        reader.read_to_string(&mut data)?;
+
+        let mut warnings = Vec::new();
+        let todos = Todo::parse(&data, Some(&mut warnings))?;
+
+        self.batch(|| {
+            if mode == ImportMode::Replace {
+                for (id, _) in self.list_tasks()? {
+                    self.delete_task(&id)?;
+                }
+            }
+
+            let mut report = ImportReport {
+                warnings: warnings.clone(),
+                ..Default::default()
+            };
+
+            for todo in todos {
+                match self.get_task(&todo.uid)? {
+                    Some(mut task) => {
+                        todo.update(&mut task);
+                        self.update_task(&todo.uid, &task)?;
+                        report.updated += 1;
+                    }
+                    None => {
+                        self.insert_task(&todo.uid, &todo.to_task(now))?;
+                        report.imported += 1;
+                    }
+                }
+            }
+
+            Ok(report)
+        })
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Splits a comma separated value, keeping escaped commas.
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut escaped = false;
+
+    for c in value.chars() {
+        if c == ',' && !escaped {
+            items.push(String::new());
+        } else {
+            items.last_mut().unwrap().push(c);
+        }
+
+        escaped = c == '\\' && !escaped;
+    }
+
+    items
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n' | 'N') => result.push('\n'),
+                Some(c) => result.push(c),
+                None => {}
+            }
+        } else {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+/// Splits a content line longer than [`LINE_LIMIT`] octets, ending every line with CRLF.
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+
+    for c in line.chars() {
+        if width + c.len_utf8() > LINE_LIMIT {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+
+        folded.push(c);
+        width += c.len_utf8();
+    }
+
+    folded.push_str("\r\n");
+    folded
+}
+
+fn unfold(data: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in data.lines() {
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ if line.is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    lines
+}
+
+/// Returns the upper-cased name and the value of a content line, dropping parameters.
+fn split_property(line: &str) -> Result<(String, &str)> {
+    let mut quoted = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => {
+                let name = line[..i].split(';').next().unwrap_or_default();
+
+                return Ok((name.to_uppercase(), &line[i + 1..]));
+            }
+            _ => {}
+        }
+    }
+
+    bail!("Invalid content line: {}", line)
+}
+
+/// A date without time is the end of that day for `DUE`, otherwise its start.
+fn parse_date(value: &str, end_of_day: bool) -> Result<NaiveDateTime> {
+    if let Some(utc) = value.strip_suffix('Z') {
+        let date = NaiveDateTime::parse_from_str(utc, DATE_TIME_FORMAT)
+            .with_context(|| format!("Invalid date: {}", value))?;
+
+        return Ok(Utc.from_utc_datetime(&date).with_timezone(&Local).naive_local());
+    }
+
+    if let Ok(date) = NaiveDateTime::parse_from_str(value, DATE_TIME_FORMAT) {
+        return Ok(date);
+    }
+
+    let date = NaiveDate::parse_from_str(value, DATE_FORMAT)
+        .with_context(|| format!("Invalid date: {}", value))?;
+
+    Ok(if end_of_day {
+        date.and_hms_opt(23, 59, 59).unwrap()
+    } else {
+        date.and_hms_opt(0, 0, 0).unwrap()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_tags;
+
+    fn date(d: u32, h: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2022, 9, d)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn calendar_round_trip() {
+        let todo = Todo {
+            uid: "V1StGXR8_Z5jdHi6B-myT".to_string(),
+            summary: "Water the plants; then rest, maybe".to_string(),
+            description: "Balcony first\nthen the kitchen, and a very long line that needs folding ąę".to_string(),
+            created: Some(date(10, 8)),
+            due: Some(date(11, 20)),
+            completed: Some(date(11, 9)),
+            done: true,
+            rrule: Some(Recurrence::Days(1)),
+            exp: Some(30),
+            categories: vec!["home".to_string(), "garden, front".to_string()],
+            priority: Some(3),
+        };
+
+        let data = Todo::write_calendar(std::slice::from_ref(&todo), date(12, 0));
+
+        assert!(data.lines().all(|l| l.len() <= LINE_LIMIT + 1));
+        assert_eq!(Todo::parse_calendar(&data).unwrap(), vec![todo]);
+    }
+
+    #[test]
+    fn parses_other_apps() {
+        let data = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VTODO\r\n\
+            UID:abc@example.com\r\n\
+            SUMMARY;LANGUAGE=en:Call\r\n \\, mom\r\n\
+            DUE;VALUE=DATE:20220911\r\n\
+            BEGIN:VALARM\r\n\
+            DESCRIPTION:Reminder\r\n\
+            END:VALARM\r\n\
+            STATUS:NEEDS-ACTION\r\n\
+            CATEGORIES:Family,Phone calls\r\n\
+            PRIORITY:2\r\n\
+            END:VTODO\r\n\
+            BEGIN:VEVENT\r\n\
+            UID:event\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+
+        let todos = Todo::parse_calendar(data).unwrap();
+
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].uid, "abc@example.com");
+        assert_eq!(todos[0].summary, "Call, mom");
+        assert_eq!(todos[0].description, "");
+        assert_eq!(todos[0].due, Some(NaiveDate::from_ymd_opt(2022, 9, 11).unwrap().and_hms_opt(23, 59, 59).unwrap()));
+        assert!(!todos[0].done);
+        assert_eq!(todos[0].categories, vec!["Family", "Phone calls"]);
+
+        let task = todos[0].to_task(NaiveDateTime::default());
+        assert_eq!(task.tags, parse_tags("family phone-calls").unwrap());
+        assert_eq!(task.priority, Priority::High);
+    }
+
+    #[test]
+    fn reimport_updates() {
+        let database = Database::temporary().unwrap();
+        let id = database
+            .create_task(&Task {
+                title: "Water".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let mut data = Vec::new();
+        database.export_ical(&mut data, date(10, 8)).unwrap();
+        let data = String::from_utf8(data).unwrap().replace("SUMMARY:Water", "SUMMARY:Watered");
+
+        let report = database.import_ical(data.as_bytes(), ImportMode::Merge, date(10, 8)).unwrap();
+
+        assert_eq!((report.imported, report.updated), (0, 1));
+        assert_eq!(database.list_tasks().unwrap().len(), 1);
+        assert_eq!(database.get_task(&id).unwrap().unwrap().title, "Watered");
+    }
+
+    #[test]
+    fn drops_unsupported_rules() {
+        let database = Database::temporary().unwrap();
+        let data = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VTODO\r\n\
+            RRULE:FREQ=YEARLY\r\n\
+            UID:birthday@example.com\r\n\
+            SUMMARY:Birthday\r\n\
+            END:VTODO\r\n\
+            BEGIN:VTODO\r\n\
+            UID:plants@example.com\r\n\
+            SUMMARY:Water\r\n\
+            RRULE:FREQ=DAILY\r\n\
+            END:VTODO\r\n\
+            END:VCALENDAR\r\n";
+
+        assert!(Todo::parse_calendar(data).is_err());
+
+        let report = database.import_ical(data.as_bytes(), ImportMode::Merge, date(10, 8)).unwrap();
+
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].starts_with("Task birthday@example.com: dropped RRULE"));
+
+        let birthday = database.get_task("birthday@example.com").unwrap().unwrap();
+        assert_eq!(birthday.title, "Birthday");
+        assert!(birthday.preferences.repeat.is_none());
+
+        let plants = database.get_task("plants@example.com").unwrap().unwrap();
+        assert_eq!(plants.preferences.repeat, Some(Recurrence::Days(1)));
+    }
+}
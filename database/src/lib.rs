@@ -1,87 +1,571 @@
 mod achievements;
+mod crypto;
+mod dates;
+mod dependencies;
+mod export;
+mod files;
+mod history;
+mod ical;
+mod migrations;
+mod projects;
+mod query;
+mod recurrence;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod store;
+mod sync;
+mod task;
+mod todotxt;
+mod undo;
+
+pub use crate::crypto::WrongPassphrase;
+pub use crate::dates::parse_deadline;
+pub use crate::dependencies::{Blocked, ChainLink};
+pub use crate::export::{ImportMode, ImportReport};
+pub use crate::files::FileStore;
+pub use crate::history::{Event, HistoryEntry};
+pub use crate::projects::{Project, ProjectId, ProjectStats};
+pub use crate::ical::Todo;
+pub use crate::query::{fuzzy_score, Sort, TaskQuery};
+pub use crate::recurrence::Recurrence;
+#[cfg(feature = "sqlite")]
+pub use crate::sqlite::SqliteStore;
+pub use crate::store::{MemoryStore, TaskStore};
+pub use crate::sync::SyncState;
+pub use crate::task::{
+    parse_tag, parse_tags, Preferences, Priority, Subtask, Task, TaskId, TaskState,
+};
+pub use crate::todotxt::SyncReport;
+pub use crate::undo::UNDO_LIMIT;
 
 use crate::achievements::Achievements;
-use anyhow::{Context, Result};
+use crate::store::{Codec, SledStore};
+use crate::history::HISTORY_TREE;
+use crate::projects::PROJECTS_TREE;
+use crate::undo::{Entry, REDO_TREE, UNDO_TREE};
+use anyhow::{bail, Context, Result};
 use bincode::config::Configuration;
+use chrono::{Local, NaiveDateTime};
 use nanoid::nanoid;
 use num_integer::Roots;
+use serde::de::DeserializeOwned;
 use serde::{Serialize, Deserialize};
-use sled::{Db, Iter};
+use sled::{Db, Tree};
+use std::cell::RefCell;
+use std::env;
+use std::path::{Path, PathBuf};
+
+const TASKS_TREE: &str = "tasks";
+const ACCOUNT_TREE: &str = "account";
+const META_TREE: &str = "meta";
+
+const ACCOUNT_KEY: &str = "account";
+const EXP_MULTIPLIERS_KEY: &str = "exp_multipliers";
+
+const DATA_DIR_ENV: &str = "TORS_DATA_DIR";
 
 pub struct Database {
-    pub db: Db,
-    pub config: Configuration,
+    db: Db,
+    config: Configuration,
+    tasks: Tree,
+    account: Tree,
+    meta: Tree,
+    undo: Tree,
+    redo: Tree,
+    history: Tree,
+    projects: Tree,
+    store: Box<dyn TaskStore>,
+    codec: Codec,
+    locked: bool,
+    /// The undo step being recorded by [`Database::batch`].
+    recording: RefCell<Option<Entry>>,
 }
 
 impl Database {
+    /// Opens the database at [`Database::default_path`].
     pub fn new() -> Result<Self> {
-        let db = sled::open(
-            dirs::home_dir()
-                .with_context(|| "Not found $HOME path")?
-                .join(".tors/"),
-        )?;
+        Self::open(Self::default_path()?)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let db = sled::open(path)
+            .with_context(|| format!("Failed open database at {}", path.display()))?;
+
+        Self::load(db)
+    }
 
+    /// Opens an empty database which is removed when dropped.
+    pub fn temporary() -> Result<Self> {
+        Self::load(sled::Config::new().temporary(true).open()?)
+    }
+
+    /// `$TORS_DATA_DIR` if set, otherwise `tors` in the user data directory
+    /// (`~/.local/share/tors` on Linux). Falls back to the legacy `~/.tors`
+    /// when only that one exists.
+    pub fn default_path() -> Result<PathBuf> {
+        if let Some(path) = env::var_os(DATA_DIR_ENV) {
+            return Ok(PathBuf::from(path));
+        }
+
+        let path = dirs::data_dir()
+            .with_context(|| "Not found user data directory")?
+            .join("tors");
+
+        if !path.exists() {
+            if let Some(legacy) = dirs::home_dir().map(|home| home.join(".tors")) {
+                if legacy.exists() {
+                    return Ok(legacy);
+                }
+            }
+        }
+
+        Ok(path)
+    }
+
+    fn load(db: Db) -> Result<Self> {
         let config = bincode::config::standard();
+        let codec = Codec::new(config);
+        let tasks = db.open_tree(TASKS_TREE)?;
 
-        Ok(Self {
+        let mut database = Self {
+            store: Box::new(SledStore::new(tasks.clone(), codec.clone())),
+            tasks,
+            account: db.open_tree(ACCOUNT_TREE)?,
+            meta: db.open_tree(META_TREE)?,
+            undo: db.open_tree(UNDO_TREE)?,
+            redo: db.open_tree(REDO_TREE)?,
+            history: db.open_tree(HISTORY_TREE)?,
+            projects: db.open_tree(PROJECTS_TREE)?,
             db,
             config,
-        })
+            codec,
+            locked: false,
+            recording: RefCell::new(None),
+        };
+
+        database.locked = database.is_encrypted()?;
+
+        if !database.locked {
+            database.prepare()?;
+        }
+
+        Ok(database)
+    }
+
+    /// Keeps tasks in `store` instead of the database itself. Tasks already
+    /// in the database are left where they are.
+    pub fn with_task_store<S: TaskStore + 'static>(mut self, store: S) -> Self {
+        self.store = Box::new(store);
+        self
     }
 
-    pub fn add<T: Serialize>(&self, value: &T) -> Result<()> {
-        let nanoid = nanoid!();
+    /// Brings an opened (and unlocked) database to the current schema.
+    fn prepare(&self) -> Result<()> {
+        migrations::migrate(self)?;
+        self.init()
+    }
 
-        self.insert(nanoid, value)?;
+    /// Creates a default account if the database doesn't have one yet.
+    /// An existing record is left untouched, even if it can't be decoded.
+    pub fn init(&self) -> Result<()> {
+        if !self.account.contains_key(ACCOUNT_KEY)? {
+            self.reset_account()?;
+        }
 
         Ok(())
     }
 
-    pub fn insert<K: AsRef<[u8]>, T: Serialize>(&self, key: K, value: &T) -> Result<()> {
-        self.db
-            .insert(key, bincode::serde::encode_to_vec(value, self.config)?)?;
+    pub fn reset_account(&self) -> Result<()> {
+        self.save_account(&Account::default())
+    }
 
-        Ok(())
+    pub fn create_task(&self, task: &Task) -> Result<TaskId> {
+        let id = nanoid!();
+
+        self.insert_task(&id, task)?;
+
+        Ok(id)
+    }
+
+    pub fn get_task(&self, id: &str) -> Result<Option<Task>> {
+        self.unlocked()?;
+        self.store.get(id)
     }
 
-    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
-        self.db.remove(key)?;
+    /// Stores a task under an id chosen by the caller, replacing any task with that id.
+    pub fn insert_task(&self, id: &str, task: &Task) -> Result<()> {
+        self.put_task(id, Some(task), Local::now().naive_local())
+    }
+
+    pub fn update_task(&self, id: &str, task: &Task) -> Result<()> {
+        self.unlocked()?;
+
+        if !self.store.contains(id)? {
+            bail!("Not found task {}", id)
+        }
+
+        self.put_task(id, Some(task), Local::now().naive_local())
+    }
+
+    pub fn delete_task(&self, id: &str) -> Result<()> {
+        self.put_task(id, None, Local::now().naive_local())
+    }
+
+    /// Stores or removes a task as one undo step, recording its changes in
+    /// the task history at `now`.
+    fn put_task(&self, id: &str, task: Option<&Task>, now: NaiveDateTime) -> Result<()> {
+        self.unlocked()?;
+
+        self.batch(|| {
+            let before = self.store.get(id)?;
+
+            match task {
+                Some(task) => self.store.insert(id, task)?,
+                None => self.store.remove(id)?,
+            }
+
+            self.record(id, now, Event::diff(before.as_ref(), task))?;
+            self.track_task(id, before, task);
+
+            Ok(())
+        })
+    }
+
+    pub fn list_tasks(&self) -> Result<Vec<(TaskId, Task)>> {
+        self.unlocked()?;
+        self.store.list()
+    }
+
+    /// Tasks matching `query`, with states as of `now`.
+    pub fn query_tasks(&self, query: &TaskQuery, now: NaiveDateTime) -> Result<Vec<(TaskId, Task)>> {
+        self.unlocked()?;
+        self.store.query(query, now)
+    }
+
+    /// Moves every repeating task whose deadline has passed to its next period
+    /// and archives completed tasks whose deadline has passed. These changes
+    /// can't be undone.
+    pub fn refresh(&self, now: NaiveDateTime) -> Result<()> {
+        for (id, mut task) in self.list_tasks()? {
+            if task.roll_over(now) {
+                self.store.insert(&id, &task)?;
+                self.record(
+                    &id,
+                    now,
+                    vec![Event::RolledOver {
+                        expire: task.preferences.expire,
+                    }],
+                )?;
+            } else if task.archived.is_none() && task.done && task.preferences.expire <= now {
+                task.archived = Some(now);
+                self.store.insert(&id, &task)?;
+                self.record(&id, now, vec![Event::Archived])?;
+            }
+        }
 
         Ok(())
     }
 
-    // pub fn contains<K: AsRef<[u8]>>(&mut self, key: K) -> bool {
-    //     self.database.contains_key(key).unwrap_or(false)
-    // }
+    pub fn archive_task(&self, id: &str, now: NaiveDateTime) -> Result<()> {
+        let mut task = self.get_task(id)?.with_context(|| format!("Not found task {}", id))?;
+
+        task.archived = Some(now);
 
-    pub fn iter(&self) -> Iter {
-        self.db.iter()
+        self.put_task(id, Some(&task), now)
+    }
+
+    /// Moves an archived task back to the list. A task whose deadline has
+    /// passed is reopened, otherwise [`Database::refresh`] would archive it again.
+    pub fn restore_task(&self, id: &str, now: NaiveDateTime) -> Result<()> {
+        let mut task = self.get_task(id)?.with_context(|| format!("Not found task {}", id))?;
+
+        task.archived = None;
+
+        if task.preferences.expire <= now {
+            task.done = false;
+        }
+
+        self.put_task(id, Some(&task), now)
+    }
+
+    /// Deletes every archived task, returning how many were removed.
+    pub fn purge_archived(&self) -> Result<usize> {
+        let archived = self
+            .list_tasks()?
+            .into_iter()
+            .filter(|(_, task)| task.archived.is_some())
+            .collect::<Vec<_>>();
+
+        self.batch(|| {
+            for (id, _) in &archived {
+                self.delete_task(id)?;
+            }
+
+            Ok(archived.len())
+        })
     }
 
     pub fn account(&self) -> Result<Account> {
-        let data = self.db.get("account")?.with_context(|| "Failed get account field")?;
+        let data = self.account.get(ACCOUNT_KEY)?.with_context(|| "Failed get account field")?;
+
+        self.decode(&data)
+            .with_context(|| "Failed decode account field")
+    }
+
+    /// Marks a task done or not done, awarding its EXP the first time it's
+    /// done. Fails with [`Blocked`] while a task it depends on isn't done.
+    pub fn set_done(&self, id: &str, done: bool, now: NaiveDateTime) -> Result<()> {
+        let mut task = self.get_task(id)?.with_context(|| format!("Not found task {}", id))?;
+
+        if done && !task.done {
+            self.check_blocked(id)?;
+        }
+
+        task.set_done(done, now);
 
-        Ok(bincode::serde::decode_from_slice(&data, self.config)?.0)
+        self.save_done(id, task, 0, now)
+    }
+
+    /// Marks a checklist item done or not done, awarding its EXP the first
+    /// time it's done. With [`Preferences::auto_complete`] the task is marked
+    /// done once every item is, unless it's blocked.
+    pub fn set_subtask_done(
+        &self,
+        id: &str,
+        index: usize,
+        done: bool,
+        now: NaiveDateTime,
+    ) -> Result<()> {
+        let mut task = self.get_task(id)?.with_context(|| format!("Not found task {}", id))?;
+        let subtask = task
+            .subtasks
+            .get_mut(index)
+            .with_context(|| format!("Not found item {} of task {}", index + 1, id))?;
+
+        subtask.done = done;
+
+        let mut exp = 0;
+
+        if done && !subtask.exp_added {
+            subtask.exp_added = true;
+            exp = subtask.exp;
+        }
+
+        if task.preferences.auto_complete
+            && task.subtasks.iter().all(|s| s.done)
+            && self.blockers(id)?.is_empty()
+        {
+            task.set_done(true, now);
+        }
+
+        self.save_done(id, task, exp, now)
+    }
+
+    /// Stores a task whose done flags changed, awarding `exp` and its own
+    /// EXP if it's done for the first time, scaled by the task priority when
+    /// multipliers are on.
+    fn save_done(&self, id: &str, mut task: Task, mut exp: u32, now: NaiveDateTime) -> Result<()> {
+        if task.done && !task.exp_added {
+            task.exp_added = true;
+            exp = exp.saturating_add(task.preferences.exp);
+        }
+
+        if self.exp_multipliers()? {
+            let scaled = u64::from(exp) * u64::from(task.priority.exp_multiplier()) / 100;
+            exp = u32::try_from(scaled).unwrap_or(u32::MAX);
+        }
+
+        self.batch(|| {
+            self.put_task(id, Some(&task), now)?;
+
+            if exp > 0 {
+                self.add_exp(exp)?;
+                self.record(id, now, vec![Event::ExpAwarded(exp)])?;
+            }
+
+            Ok(())
+        })
     }
 
     pub fn add_exp(&self, exp: u32) -> Result<()> {
         let mut account = self.account()?;
 
-        account.exp += exp;
+        account.exp = account.exp.saturating_add(exp);
         account.lvl = (account.exp/10).sqrt().saturating_sub(1);
 
-        self.db.insert(
-            "account",
-            bincode::serde::encode_to_vec(account, self.config)?,
-        )?;
+        self.save_account(&account)
+    }
+
+    /// Whether tasks of a higher [`Priority`] award more EXP, off by default.
+    pub fn exp_multipliers(&self) -> Result<bool> {
+        Ok(self
+            .meta
+            .get(EXP_MULTIPLIERS_KEY)?
+            .is_some_and(|data| data.as_ref() == [1]))
+    }
+
+    pub fn set_exp_multipliers(&self, on: bool) -> Result<()> {
+        self.meta.insert(EXP_MULTIPLIERS_KEY, &[on as u8])?;
 
         Ok(())
     }
+
+    fn save_account(&self, account: &Account) -> Result<()> {
+        self.batch(|| {
+            let before = self.account.insert(ACCOUNT_KEY, self.encode(account)?)?;
+
+            if let Some(Ok(before)) = before.map(|data| self.decode(&data)) {
+                self.track_account(before, account);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        self.unlocked()?;
+        self.codec.encode(value)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        self.unlocked()?;
+        self.codec.decode(data)
+    }
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Account {
     pub lvl: u32,
     pub exp: u32,
     pub achievements: Vec<Achievements>,
 }
+
+impl Account {
+    /// EXP still needed to reach the next level.
+    pub fn next_level_exp(&self) -> u32 {
+        (10 * (self.lvl + 2).pow(2)).saturating_sub(self.exp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database(db: Db) -> Database {
+        Database::load(db).unwrap()
+    }
+
+    fn sled() -> Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    #[test]
+    fn fresh_database_has_account() {
+        let database = Database::temporary().unwrap();
+
+        let account = database.account().unwrap();
+        assert_eq!(account.lvl, 0);
+        assert_eq!(account.exp, 0);
+        assert!(account.achievements.is_empty());
+
+        database.add_exp(50).unwrap();
+        assert_eq!(database.account().unwrap().exp, 50);
+    }
+
+    #[test]
+    fn existing_account_is_kept() {
+        let db = sled();
+
+        database(db.clone()).add_exp(250).unwrap();
+
+        let database = database(db);
+        database.init().unwrap();
+
+        let account = database.account().unwrap();
+        assert_eq!(account.exp, 250);
+        assert_eq!(account.lvl, 4);
+    }
+
+    #[test]
+    fn corrupt_account_can_be_reset() {
+        let db = sled();
+        db.open_tree(ACCOUNT_TREE)
+            .unwrap()
+            .insert(ACCOUNT_KEY, &[0xff; 3])
+            .unwrap();
+
+        let database = database(db);
+
+        assert!(database.account().is_err());
+        assert!(database.add_exp(10).is_err());
+
+        database.reset_account().unwrap();
+        database.add_exp(10).unwrap();
+
+        assert_eq!(database.account().unwrap().exp, 10);
+    }
+
+    #[test]
+    fn checklist_awards_exp_and_completes() {
+        let database = Database::temporary().unwrap();
+        let item = |title: &str| Subtask {
+            title: title.to_string(),
+            exp: 5,
+            ..Default::default()
+        };
+        let id = database
+            .create_task(&Task {
+                subtasks: vec![item("Balcony"), item("Kitchen")],
+                preferences: Preferences {
+                    auto_complete: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .unwrap();
+        let now = NaiveDateTime::default();
+
+        database.set_subtask_done(&id, 0, true, now).unwrap();
+        database.set_subtask_done(&id, 0, false, now).unwrap();
+        database.set_subtask_done(&id, 0, true, now).unwrap();
+        assert_eq!(database.account().unwrap().exp, 5);
+        assert_eq!(database.get_task(&id).unwrap().unwrap().progress(), Some((1, 2)));
+
+        database.set_subtask_done(&id, 1, true, now).unwrap();
+        assert!(database.get_task(&id).unwrap().unwrap().done);
+        assert_eq!(database.account().unwrap().exp, 35);
+
+        assert!(database.set_subtask_done(&id, 2, true, now).is_err());
+    }
+
+    #[test]
+    fn priority_multiplies_exp() {
+        let database = Database::temporary().unwrap();
+        let task = |priority| Task {
+            priority,
+            ..Default::default()
+        };
+        let now = NaiveDateTime::default();
+
+        let a = database.create_task(&task(Priority::Urgent)).unwrap();
+        database.set_done(&a, true, now).unwrap();
+        assert_eq!(database.account().unwrap().exp, 25);
+
+        assert!(!database.exp_multipliers().unwrap());
+        database.set_exp_multipliers(true).unwrap();
+        assert!(database.exp_multipliers().unwrap());
+
+        let b = database.create_task(&task(Priority::Urgent)).unwrap();
+        let c = database.create_task(&task(Priority::Medium)).unwrap();
+        database.set_done(&b, true, now).unwrap();
+        database.set_done(&c, true, now).unwrap();
+        assert_eq!(database.account().unwrap().exp, 25 + 50 + 31);
+
+        let mut huge = task(Priority::Urgent);
+        huge.preferences.exp = u32::MAX;
+        let d = database.create_task(&huge).unwrap();
+        database.set_done(&d, true, now).unwrap();
+        assert_eq!(database.account().unwrap().exp, u32::MAX);
+    }
+}
@@ -0,0 +1,659 @@
+use crate::{Database, Event, HistoryEntry, Preferences, Priority, Recurrence, Task, ACCOUNT_KEY};
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::transaction::TransactionError;
+use sled::Transactional;
+use std::collections::BTreeSet;
+
+/// Version of the on-disk layout written by this build.
+pub(crate) const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+type Migration = fn(&Database) -> Result<()>;
+
+/// `MIGRATIONS[n]` upgrades the database from version `n` to `n + 1`.
+///
+/// Migrations run in order on open and the new version is stored
+/// after each one, so every migration must be safe to run again
+/// if the previous attempt was interrupted.
+const MIGRATIONS: [Migration; 8] = [
+    split_default_tree,
+    repeat_rules,
+    archive,
+    subtasks,
+    projects,
+    tags,
+    priority,
+    dependencies,
+];
+
+pub(crate) fn migrate(database: &Database) -> Result<()> {
+    let version = schema_version(database)?;
+
+    if version > SCHEMA_VERSION {
+        bail!(
+            "Database schema version {} is newer than supported version {}",
+            version,
+            SCHEMA_VERSION
+        )
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(database)
+            .with_context(|| format!("Failed migrate database from version {}", from))?;
+
+        database
+            .meta
+            .insert(SCHEMA_VERSION_KEY, &(from as u32 + 1).to_be_bytes())?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn schema_version(database: &Database) -> Result<u32> {
+    match database.meta.get(SCHEMA_VERSION_KEY)? {
+        Some(data) => Ok(u32::from_be_bytes(
+            data.as_ref()
+                .try_into()
+                .with_context(|| "Invalid schema version record")?,
+        )),
+        None => Ok(0),
+    }
+}
+
+/// Version 0 kept every record in the default tree: the `account` key
+/// goes to the account tree, everything else is a task.
+fn split_default_tree(database: &Database) -> Result<()> {
+    let records = database.db.iter().collect::<sled::Result<Vec<_>>>()?;
+
+    (&*database.db, &database.tasks, &database.account)
+        .transaction(|(default, tasks, account)| {
+            for (key, value) in &records {
+                if key == ACCOUNT_KEY.as_bytes() {
+                    account.insert(key, value)?;
+                } else {
+                    tasks.insert(key, value)?;
+                }
+
+                default.remove(key)?;
+            }
+
+            Ok(())
+        })
+        .map_err(|e: TransactionError| anyhow::anyhow!(e))?;
+
+    Ok(())
+}
+
+/// Version 1 only had a `daily_repeat` flag and didn't record completions.
+fn repeat_rules(database: &Database) -> Result<()> {
+    rewrite_tasks(database, 2, |task: v1::Task| v2::Task {
+        title: task.title,
+        description: task.description,
+        done: task.done,
+        exp_added: task.exp_added,
+        creation_date: task.creation_date,
+        preferences: v2::Preferences {
+            repeat: task.preferences.daily_repeat.then_some(Recurrence::Days(1)),
+            expire: task.preferences.expire,
+            exp: task.preferences.exp,
+        },
+        completions: Vec::new(),
+    })
+}
+
+/// Version 2 had no archive.
+fn archive(database: &Database) -> Result<()> {
+    rewrite_tasks(database, 3, |task: v2::Task| v3::Task {
+        title: task.title,
+        description: task.description,
+        done: task.done,
+        exp_added: task.exp_added,
+        creation_date: task.creation_date,
+        preferences: task.preferences,
+        completions: task.completions,
+        archived: None,
+    })
+}
+
+/// Version 3 had no checklists and kept history entries in bincode, which
+/// numbers the events by their position and so broke on every new event.
+fn subtasks(database: &Database) -> Result<()> {
+    history_json(database)?;
+
+    rewrite_tasks(database, 4, |task: v3::Task| v4::Task {
+        title: task.title,
+        description: task.description,
+        done: task.done,
+        exp_added: task.exp_added,
+        creation_date: task.creation_date,
+        preferences: v4::Preferences {
+            repeat: task.preferences.repeat,
+            expire: task.preferences.expire,
+            exp: task.preferences.exp,
+            auto_complete: false,
+        },
+        completions: task.completions,
+        archived: task.archived,
+        subtasks: Vec::new(),
+    })
+}
+
+/// Stores every history entry of version 3 as JSON in a single transaction.
+fn history_json(database: &Database) -> Result<()> {
+    let entries = database
+        .history
+        .iter()
+        .map(|record| {
+            let (key, data) = record?;
+
+            // Converted by an interrupted earlier run
+            if database.decode_entry(&data).is_ok() {
+                return Ok(None);
+            }
+
+            let entry: v3::HistoryEntry =
+                database.decode(&data).with_context(|| "Failed decode history")?;
+            let event = match entry.event {
+                v3::Event::Created { title } => Event::Created { title },
+                v3::Event::Deleted => Event::Deleted,
+                v3::Event::Title { before, after } => Event::Title { before, after },
+                v3::Event::Description { before, after } => Event::Description { before, after },
+                v3::Event::Expire { before, after } => Event::Expire { before, after },
+                v3::Event::Exp { before, after } => Event::Exp { before, after },
+                v3::Event::Repeat { before, after } => Event::Repeat { before, after },
+                v3::Event::Done(done) => Event::Done(done),
+                v3::Event::ExpAwarded(exp) => Event::ExpAwarded(exp),
+                v3::Event::Archived => Event::Archived,
+                v3::Event::Restored => Event::Restored,
+                v3::Event::RolledOver { expire } => Event::RolledOver { expire },
+            };
+            let entry = HistoryEntry {
+                time: entry.time,
+                event,
+            };
+
+            Ok(Some((key, database.encode_entry(&entry)?)))
+        })
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?;
+
+    database
+        .history
+        .transaction(|tree| {
+            for (key, data) in &entries {
+                tree.insert(key, data.as_slice())?;
+            }
+
+            Ok(())
+        })
+        .map_err(|e: TransactionError| anyhow::anyhow!(e))?;
+
+    Ok(())
+}
+
+/// Version 4 had no projects.
+fn projects(database: &Database) -> Result<()> {
+    rewrite_tasks(database, 5, |task: v4::Task| v5::Task {
+        title: task.title,
+        description: task.description,
+        done: task.done,
+        exp_added: task.exp_added,
+        creation_date: task.creation_date,
+        preferences: task.preferences,
+        completions: task.completions,
+        archived: task.archived,
+        subtasks: task.subtasks,
+        project: None,
+    })
+}
+
+/// Version 5 had no tags.
+fn tags(database: &Database) -> Result<()> {
+    rewrite_tasks(database, 6, |task: v5::Task| v6::Task {
+        title: task.title,
+        description: task.description,
+        done: task.done,
+        exp_added: task.exp_added,
+        creation_date: task.creation_date,
+        preferences: task.preferences,
+        completions: task.completions,
+        archived: task.archived,
+        subtasks: task.subtasks,
+        project: task.project,
+        tags: BTreeSet::new(),
+    })
+}
+
+/// Version 6 had no priorities.
+fn priority(database: &Database) -> Result<()> {
+    rewrite_tasks(database, 7, |task: v6::Task| v7::Task {
+        title: task.title,
+        description: task.description,
+        done: task.done,
+        exp_added: task.exp_added,
+        creation_date: task.creation_date,
+        preferences: task.preferences,
+        completions: task.completions,
+        archived: task.archived,
+        subtasks: task.subtasks,
+        project: task.project,
+        tags: task.tags,
+        priority: Priority::None,
+    })
+}
+
+/// Version 7 had no dependencies.
+fn dependencies(database: &Database) -> Result<()> {
+    rewrite_tasks(database, 8, |task: v7::Task| Task {
+        title: task.title,
+        description: task.description,
+        done: task.done,
+        exp_added: task.exp_added,
+        creation_date: task.creation_date,
+        preferences: Preferences {
+            repeat: task.preferences.repeat,
+            expire: task.preferences.expire,
+            exp: task.preferences.exp,
+            auto_complete: task.preferences.auto_complete,
+        },
+        completions: task.completions,
+        archived: task.archived,
+        subtasks: task.subtasks,
+        project: task.project,
+        tags: task.tags,
+        priority: task.priority,
+        depends_on: BTreeSet::new(),
+    })
+}
+
+/// Decodes every task with the `Old` layout and stores it converted,
+/// together with the new schema version in a single transaction.
+fn rewrite_tasks<Old, New, F>(database: &Database, version: u32, convert: F) -> Result<()>
+where
+    Old: DeserializeOwned,
+    New: Serialize,
+    F: Fn(Old) -> New,
+{
+    let tasks = database
+        .tasks
+        .iter()
+        .map(|v| {
+            let (id, data) = v?;
+            let task = convert(database.decode(&data).with_context(|| {
+                format!("Failed decode task {}", String::from_utf8_lossy(&id))
+            })?);
+
+            Ok((id, database.encode(&task)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    (&database.tasks, &database.meta)
+        .transaction(|(tree, meta)| {
+            for (id, data) in &tasks {
+                tree.insert(id, data.as_slice())?;
+            }
+
+            meta.insert(SCHEMA_VERSION_KEY, &version.to_be_bytes())?;
+
+            Ok(())
+        })
+        .map_err(|e: TransactionError| anyhow::anyhow!(e))?;
+
+    Ok(())
+}
+
+/// Record layout of schema version 1, frozen as it was written.
+mod v1 {
+    use chrono::NaiveDateTime;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    pub struct Task {
+        pub title: String,
+        pub description: String,
+        pub done: bool,
+        pub exp_added: bool,
+        pub creation_date: NaiveDateTime,
+        pub preferences: Preferences,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Preferences {
+        pub daily_repeat: bool,
+        pub expire: NaiveDateTime,
+        pub exp: u32,
+    }
+}
+
+/// Record layout of schema version 2, frozen as it was written.
+mod v2 {
+    use crate::Recurrence;
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Task {
+        pub title: String,
+        pub description: String,
+        pub done: bool,
+        pub exp_added: bool,
+        pub creation_date: NaiveDateTime,
+        pub preferences: Preferences,
+        pub completions: Vec<NaiveDateTime>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Preferences {
+        pub repeat: Option<Recurrence>,
+        pub expire: NaiveDateTime,
+        pub exp: u32,
+    }
+}
+
+/// Record layout of schema version 3, frozen as it was written.
+mod v3 {
+    use super::v2::Preferences;
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Task {
+        pub title: String,
+        pub description: String,
+        pub done: bool,
+        pub exp_added: bool,
+        pub creation_date: NaiveDateTime,
+        pub preferences: Preferences,
+        pub completions: Vec<NaiveDateTime>,
+        pub archived: Option<NaiveDateTime>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct HistoryEntry {
+        pub time: NaiveDateTime,
+        pub event: Event,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum Event {
+        Created { title: String },
+        Deleted,
+        Title { before: String, after: String },
+        Description { before: String, after: String },
+        Expire { before: NaiveDateTime, after: NaiveDateTime },
+        Exp { before: u32, after: u32 },
+        Repeat { before: Option<String>, after: Option<String> },
+        Done(bool),
+        ExpAwarded(u32),
+        Archived,
+        Restored,
+        RolledOver { expire: NaiveDateTime },
+    }
+}
+
+/// Record layout of schema version 4, frozen as it was written.
+mod v4 {
+    use crate::{Recurrence, Subtask};
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Task {
+        pub title: String,
+        pub description: String,
+        pub done: bool,
+        pub exp_added: bool,
+        pub creation_date: NaiveDateTime,
+        pub preferences: Preferences,
+        pub completions: Vec<NaiveDateTime>,
+        pub archived: Option<NaiveDateTime>,
+        pub subtasks: Vec<Subtask>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Preferences {
+        pub repeat: Option<Recurrence>,
+        pub expire: NaiveDateTime,
+        pub exp: u32,
+        pub auto_complete: bool,
+    }
+}
+
+/// Record layout of schema version 5, frozen as it was written.
+mod v5 {
+    use super::v4::Preferences;
+    use crate::{ProjectId, Subtask};
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Task {
+        pub title: String,
+        pub description: String,
+        pub done: bool,
+        pub exp_added: bool,
+        pub creation_date: NaiveDateTime,
+        pub preferences: Preferences,
+        pub completions: Vec<NaiveDateTime>,
+        pub archived: Option<NaiveDateTime>,
+        pub subtasks: Vec<Subtask>,
+        pub project: Option<ProjectId>,
+    }
+}
+
+/// Record layout of schema version 6, frozen as it was written.
+mod v6 {
+    use super::v4::Preferences;
+    use crate::{ProjectId, Subtask};
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeSet;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Task {
+        pub title: String,
+        pub description: String,
+        pub done: bool,
+        pub exp_added: bool,
+        pub creation_date: NaiveDateTime,
+        pub preferences: Preferences,
+        pub completions: Vec<NaiveDateTime>,
+        pub archived: Option<NaiveDateTime>,
+        pub subtasks: Vec<Subtask>,
+        pub project: Option<ProjectId>,
+        pub tags: BTreeSet<String>,
+    }
+}
+
+/// Record layout of schema version 7, frozen as it was written.
+mod v7 {
+    use super::v4::Preferences;
+    use crate::{Priority, ProjectId, Subtask};
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeSet;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Task {
+        pub title: String,
+        pub description: String,
+        pub done: bool,
+        pub exp_added: bool,
+        pub creation_date: NaiveDateTime,
+        pub preferences: Preferences,
+        pub completions: Vec<NaiveDateTime>,
+        pub archived: Option<NaiveDateTime>,
+        pub subtasks: Vec<Subtask>,
+        pub project: Option<ProjectId>,
+        pub tags: BTreeSet<String>,
+        pub priority: Priority,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::achievements::Achievements;
+    use chrono::NaiveDate;
+
+    const V0_TASK: &[u8] = include_bytes!("../fixtures/v0/task.bin");
+    const V0_ACCOUNT: &[u8] = include_bytes!("../fixtures/v0/account.bin");
+
+    fn sled() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    fn v0_database() -> sled::Db {
+        let db = sled();
+
+        db.insert("V1StGXR8_Z5jdHi6B-myT", V0_TASK).unwrap();
+        db.insert(ACCOUNT_KEY, V0_ACCOUNT).unwrap();
+
+        db
+    }
+
+    #[test]
+    fn upgrades_v0_fixtures() {
+        let database = Database::load(v0_database()).unwrap();
+
+        assert_eq!(schema_version(&database).unwrap(), SCHEMA_VERSION);
+        assert!(database.db.is_empty());
+
+        let tasks = database.list_tasks().unwrap();
+        assert_eq!(tasks.len(), 1);
+
+        let (id, task) = &tasks[0];
+        let date = |d, h| {
+            NaiveDate::from_ymd_opt(2022, 9, d)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+
+        assert_eq!(id, "V1StGXR8_Z5jdHi6B-myT");
+        assert_eq!(task.title, "Water the plants");
+        assert_eq!(task.description, "Balcony first\nthen the kitchen");
+        assert!(task.done);
+        assert!(task.exp_added);
+        assert_eq!(task.creation_date, date(10, 8));
+        assert_eq!(task.preferences.repeat, Some(Recurrence::Days(1)));
+        assert!(task.completions.is_empty());
+        assert!(task.archived.is_none());
+        assert!(task.subtasks.is_empty());
+        assert!(task.project.is_none());
+        assert!(task.tags.is_empty());
+        assert_eq!(task.priority, Priority::None);
+        assert!(task.depends_on.is_empty());
+        assert!(!task.preferences.auto_complete);
+        assert_eq!(task.preferences.expire, date(11, 20));
+        assert_eq!(task.preferences.exp, 30);
+
+        let account = database.account().unwrap();
+        assert_eq!(account.lvl, 1);
+        assert_eq!(account.exp, 45);
+        assert!(matches!(
+            account.achievements.as_slice(),
+            [Achievements::FirstTask]
+        ));
+    }
+
+    #[test]
+    fn reopening_keeps_data() {
+        let db = v0_database();
+
+        drop(Database::load(db.clone()).unwrap());
+        let database = Database::load(db).unwrap();
+
+        assert_eq!(schema_version(&database).unwrap(), SCHEMA_VERSION);
+        assert_eq!(database.list_tasks().unwrap().len(), 1);
+        assert_eq!(database.account().unwrap().exp, 45);
+    }
+
+    #[test]
+    fn converts_bincode_history() {
+        let db = sled();
+        let time = NaiveDate::from_ymd_opt(2022, 9, 10)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let text = |text: &str| text.to_string();
+        let repeat = || Some(text("every day"));
+        let (old, expected): (Vec<_>, Vec<_>) = [
+            (
+                v3::Event::Created { title: text("Water") },
+                Event::Created { title: text("Water") },
+            ),
+            (v3::Event::Deleted, Event::Deleted),
+            (
+                v3::Event::Title { before: text("Water"), after: text("Plants") },
+                Event::Title { before: text("Water"), after: text("Plants") },
+            ),
+            (
+                v3::Event::Description { before: text(""), after: text("Balcony") },
+                Event::Description { before: text(""), after: text("Balcony") },
+            ),
+            (
+                v3::Event::Expire { before: time, after: time },
+                Event::Expire { before: time, after: time },
+            ),
+            (
+                v3::Event::Exp { before: 25, after: 30 },
+                Event::Exp { before: 25, after: 30 },
+            ),
+            (
+                v3::Event::Repeat { before: None, after: repeat() },
+                Event::Repeat { before: None, after: repeat() },
+            ),
+            (v3::Event::Done(true), Event::Done(true)),
+            (v3::Event::ExpAwarded(30), Event::ExpAwarded(30)),
+            (v3::Event::Archived, Event::Archived),
+            (v3::Event::Restored, Event::Restored),
+            (
+                v3::Event::RolledOver { expire: time },
+                Event::RolledOver { expire: time },
+            ),
+        ]
+        .into_iter()
+        .unzip();
+        let history = db.open_tree("history").unwrap();
+
+        for (i, event) in old.into_iter().enumerate() {
+            let entry = v3::HistoryEntry { time, event };
+            let data = bincode::serde::encode_to_vec(&entry, bincode::config::standard()).unwrap();
+            let mut key = b"task\0".to_vec();
+            key.extend((i as u64).to_be_bytes());
+
+            history.insert(key, data).unwrap();
+        }
+
+        db.open_tree("meta")
+            .unwrap()
+            .insert(SCHEMA_VERSION_KEY, &3u32.to_be_bytes())
+            .unwrap();
+
+        let database = Database::load(db).unwrap();
+        let events = database
+            .task_history("task")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect::<Vec<_>>();
+
+        assert_eq!(events, expected);
+        assert_eq!(database.streak(time).unwrap(), 1);
+    }
+
+    #[test]
+    fn rejects_newer_schema() {
+        let db = sled();
+        db.open_tree("meta")
+            .unwrap()
+            .insert(SCHEMA_VERSION_KEY, &(SCHEMA_VERSION + 1).to_be_bytes())
+            .unwrap();
+
+        assert!(Database::load(db).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Named task lists.
+//!
+//! Projects are kept in the `projects` tree of sled whatever the task store,
+//! encrypted like the account. A task belongs to at most one project through
+//! [`Task::project`].
+
+use crate::{Database, Task, TaskQuery, TaskState};
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDateTime;
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+
+pub(crate) const PROJECTS_TREE: &str = "projects";
+
+pub type ProjectId = String;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Project {
+    pub name: String,
+    pub creation_date: NaiveDateTime,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProjectStats {
+    /// Tasks not archived.
+    pub tasks: usize,
+    pub done: usize,
+    pub overdue: usize,
+    pub archived: usize,
+    /// Experience of done tasks and checklist items, archived ones included,
+    /// without priority multipliers.
+    pub exp: u32,
+}
+
+impl ProjectStats {
+    fn of(tasks: &[Task], now: NaiveDateTime) -> Self {
+        let mut stats = Self::default();
+
+        for task in tasks {
+            match task.state(now) {
+                TaskState::Archived => stats.archived += 1,
+                TaskState::Completed => stats.done += 1,
+                TaskState::Overdue => stats.overdue += 1,
+                TaskState::Active => {}
+            }
+
+            if task.archived.is_none() {
+                stats.tasks += 1;
+            }
+
+            if task.exp_added {
+                stats.exp += task.preferences.exp;
+            }
+
+            stats.exp += task
+                .subtasks
+                .iter()
+                .filter(|s| s.exp_added)
+                .map(|s| s.exp)
+                .sum::<u32>();
+        }
+
+        stats
+    }
+}
+
+impl Database {
+    pub fn create_project(&self, name: &str, now: NaiveDateTime) -> Result<ProjectId> {
+        self.check_project_name(name)?;
+
+        let id = nanoid!();
+        let project = Project {
+            name: name.to_string(),
+            creation_date: now,
+        };
+
+        self.projects.insert(&id, self.encode(&project)?)?;
+
+        Ok(id)
+    }
+
+    pub fn get_project(&self, id: &str) -> Result<Option<Project>> {
+        match self.projects.get(id)? {
+            Some(data) => Ok(Some(
+                self.decode(&data)
+                    .with_context(|| format!("Failed decode project {}", id))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Projects in the order they were created.
+    pub fn projects(&self) -> Result<Vec<(ProjectId, Project)>> {
+        let mut projects = self
+            .projects
+            .iter()
+            .map(|v| {
+                let (id, data) = v?;
+                let id = String::from_utf8(id.to_vec())?;
+                let project: Project = self
+                    .decode(&data)
+                    .with_context(|| format!("Failed decode project {}", id))?;
+
+                Ok((id, project))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        projects.sort_by(|(id1, p1), (id2, p2)| {
+            p1.creation_date.cmp(&p2.creation_date).then(id1.cmp(id2))
+        });
+
+        Ok(projects)
+    }
+
+    /// Finds a project by its name, ignoring case.
+    pub fn find_project(&self, name: &str) -> Result<Option<(ProjectId, Project)>> {
+        Ok(self
+            .projects()?
+            .into_iter()
+            .find(|(_, project)| project.name.eq_ignore_ascii_case(name)))
+    }
+
+    pub fn rename_project(&self, id: &str, name: &str) -> Result<()> {
+        let mut project = self
+            .get_project(id)?
+            .with_context(|| format!("Not found project {}", id))?;
+
+        if !project.name.eq_ignore_ascii_case(name) {
+            self.check_project_name(name)?;
+        }
+
+        project.name = name.to_string();
+        self.projects.insert(id, self.encode(&project)?)?;
+
+        Ok(())
+    }
+
+    /// Removes a project, its tasks stay without a project. Undoing it
+    /// brings back both the project and its tasks.
+    pub fn delete_project(&self, id: &str) -> Result<()> {
+        let project = self
+            .get_project(id)?
+            .with_context(|| format!("Not found project {}", id))?;
+
+        let query = TaskQuery {
+            project: Some(id.to_string()),
+            ..Default::default()
+        };
+
+        self.batch(|| {
+            for (task_id, mut task) in self.store.query(&query, NaiveDateTime::default())? {
+                task.project = None;
+                self.update_task(&task_id, &task)?;
+            }
+
+            self.projects.remove(id)?;
+            self.track_project(id, Some(project), None);
+
+            Ok(())
+        })
+    }
+
+    pub fn project_stats(&self, id: &str, now: NaiveDateTime) -> Result<ProjectStats> {
+        let query = TaskQuery {
+            project: Some(id.to_string()),
+            ..Default::default()
+        };
+        let tasks = self
+            .query_tasks(&query, now)?
+            .into_iter()
+            .map(|(_, task)| task)
+            .collect::<Vec<_>>();
+
+        Ok(ProjectStats::of(&tasks, now))
+    }
+
+    fn check_project_name(&self, name: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            bail!("Project name can't be empty")
+        }
+
+        if self.find_project(name)?.is_some() {
+            bail!("Project {} already exists", name)
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projects_group_tasks() {
+        let database = Database::temporary().unwrap();
+        let now = NaiveDateTime::default();
+
+        let sprint = database.create_project("Sprint", now).unwrap();
+        let chores = database.create_project("Chores", now).unwrap();
+        assert!(database.create_project("sprint", now).is_err());
+        assert!(database.create_project(" ", now).is_err());
+
+        let task = |project: &str| Task {
+            project: Some(project.to_string()),
+            ..Default::default()
+        };
+        let a = database.create_task(&task(&sprint)).unwrap();
+        database.create_task(&task(&sprint)).unwrap();
+        database.create_task(&task(&chores)).unwrap();
+        database.set_done(&a, true, now).unwrap();
+
+        let stats = database.project_stats(&sprint, now).unwrap();
+        assert_eq!(stats.tasks, 2);
+        assert_eq!(stats.done, 1);
+        assert_eq!(stats.exp, 25);
+
+        database.rename_project(&chores, "Home").unwrap();
+        assert_eq!(database.find_project("home").unwrap().unwrap().0, chores);
+        assert!(database.rename_project(&chores, "Sprint").is_err());
+
+        database.delete_project(&sprint).unwrap();
+        assert_eq!(database.projects().unwrap().len(), 1);
+        assert!(database.get_task(&a).unwrap().unwrap().project.is_none());
+    }
+
+    #[test]
+    fn undo_restores_deleted_project() {
+        let database = Database::temporary().unwrap();
+        let id = database
+            .create_project("Sprint", NaiveDateTime::default())
+            .unwrap();
+        let task = database
+            .create_task(&Task {
+                project: Some(id.clone()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        database.delete_project(&id).unwrap();
+        assert!(database.undo().unwrap());
+        assert_eq!(database.get_project(&id).unwrap().unwrap().name, "Sprint");
+        assert_eq!(database.get_task(&task).unwrap().unwrap().project, Some(id.clone()));
+
+        assert!(database.redo().unwrap());
+        assert!(database.get_project(&id).unwrap().is_none());
+        assert!(database.get_task(&task).unwrap().unwrap().project.is_none());
+    }
+}
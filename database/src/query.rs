@@ -0,0 +1,419 @@
+use crate::{parse_tag, ProjectId, Task, TaskId, TaskState};
+use anyhow::{bail, Context, Error, Result};
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Order of [`Database::query_tasks`](crate::Database::query_tasks) results.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sort {
+    #[default]
+    Creation,
+    Due,
+    /// Highest priority first.
+    Priority,
+    Title,
+    /// Tasks not done first.
+    DoneLast,
+}
+
+impl Sort {
+    pub const ALL: [Sort; 5] = [
+        Sort::Creation,
+        Sort::Due,
+        Sort::Priority,
+        Sort::Title,
+        Sort::DoneLast,
+    ];
+
+    /// The sort after this one, wrapping around.
+    pub fn next(self) -> Self {
+        let index = Sort::ALL.iter().position(|s| *s == self).unwrap_or_default();
+
+        Sort::ALL[(index + 1) % Sort::ALL.len()]
+    }
+}
+
+impl Display for Sort {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Sort::Creation => "creation",
+            Sort::Due => "due",
+            Sort::Priority => "priority",
+            Sort::Title => "title",
+            Sort::DoneLast => "done-last",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Sort {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+
+        match Sort::ALL.iter().find(|sort| sort.to_string() == s) {
+            Some(sort) => Ok(*sort),
+            None => bail!(
+                "Invalid sort: {}, expected creation, due, priority, title or done-last",
+                s
+            ),
+        }
+    }
+}
+
+/// Which tasks to list and in what order. The default matches every task.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TaskQuery {
+    /// Tasks in any of these states; every state when empty.
+    pub states: Vec<TaskState>,
+    pub done: Option<bool>,
+    /// Deadline before this date.
+    pub due_before: Option<NaiveDateTime>,
+    /// Deadline at or after this date.
+    pub due_after: Option<NaiveDateTime>,
+    /// Case-insensitive text in the title or description.
+    pub text: Option<String>,
+    /// Fuzzy text in the title or description, see [`fuzzy_score`]. Tasks
+    /// that match better come first, then [`TaskQuery::sort`] applies.
+    pub search: Option<String>,
+    /// Tasks of this project.
+    pub project: Option<ProjectId>,
+    /// Tasks with every one of these tags.
+    pub tags: Vec<String>,
+    /// Tasks with none of these tags.
+    pub without_tags: Vec<String>,
+    pub sort: Sort,
+}
+
+impl TaskQuery {
+    pub fn matches(&self, task: &Task, now: NaiveDateTime) -> bool {
+        let expire = task.preferences.expire;
+
+        (self.states.is_empty() || self.states.contains(&task.state(now)))
+            && self.project.as_ref().is_none_or(|project| task.project.as_ref() == Some(project))
+            && self.tags.iter().all(|tag| task.tags.contains(tag))
+            && !self.without_tags.iter().any(|tag| task.tags.contains(tag))
+            && self.done.is_none_or(|done| task.done == done)
+            && self.due_before.is_none_or(|date| expire < date)
+            && self.due_after.is_none_or(|date| expire >= date)
+            && self.text.as_ref().is_none_or(|text| {
+                let text = text.to_lowercase();
+
+                task.title.to_lowercase().contains(&text)
+                    || task.description.to_lowercase().contains(&text)
+            })
+            && self.search_score(task).is_some()
+    }
+
+    /// How well a task matches [`TaskQuery::search`], a title match counting
+    /// double. Every task matches without a search.
+    pub fn search_score(&self, task: &Task) -> Option<u32> {
+        match &self.search {
+            Some(search) => fuzzy_score(search, &task.title)
+                .map(|score| score * 2)
+                .max(fuzzy_score(search, &task.description)),
+            None => Some(0),
+        }
+    }
+
+    /// Adds the terms of a query like `done:no due:<2026-11-01 tag:work "call mom" plnts`:
+    ///
+    /// - `done:yes` or `done:no`
+    /// - `due:<date`, `due:<=date`, `due:>date`, `due:>=date`, or `due:date`
+    ///   for that day, dates being `YYYY-MM-DD`
+    /// - `tag:name` or `#name`, a leading `-` excluding the tag
+    /// - `"text"` for that text in the title or description
+    /// - any other word for a fuzzy search, see [`TaskQuery::search`]
+    pub fn filter(&mut self, input: &str) -> Result<()> {
+        let mut search = Vec::new();
+
+        for (term, quoted) in terms(input)? {
+            if quoted {
+                if self.text.is_some() {
+                    bail!("Only one quoted text is supported")
+                }
+
+                self.text = Some(term);
+                continue;
+            }
+
+            let (negated, word) = match term.strip_prefix('-') {
+                Some(word) if word.starts_with('#') || word.starts_with("tag:") => (true, word),
+                _ => (false, term.as_str()),
+            };
+
+            match word.split_once(':') {
+                Some(("tag", tag)) => self.push_tag(tag, negated)?,
+                Some(("done", value)) => {
+                    self.done = Some(match value.to_lowercase().as_str() {
+                        "yes" | "true" => true,
+                        "no" | "false" => false,
+                        _ => bail!("Invalid done value: {}, expected yes or no", value),
+                    })
+                }
+                Some(("due", value)) => self.filter_due(value)?,
+                _ if word.starts_with('#') => self.push_tag(word, negated)?,
+                _ => search.push(word.to_string()),
+            }
+        }
+
+        if !search.is_empty() {
+            self.search = Some(search.join(" "));
+        }
+
+        Ok(())
+    }
+
+    fn push_tag(&mut self, tag: &str, negated: bool) -> Result<()> {
+        let tag = parse_tag(tag)?;
+
+        match negated {
+            true => self.without_tags.push(tag),
+            false => self.tags.push(tag),
+        }
+
+        Ok(())
+    }
+
+    fn filter_due(&mut self, value: &str) -> Result<()> {
+        let (op, date) = ["<=", ">=", "<", ">"]
+            .iter()
+            .find_map(|op| Some((*op, value.strip_prefix(op)?)))
+            .unwrap_or(("", value));
+        let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .with_context(|| format!("Invalid due date: {}, expected YYYY-MM-DD", date))?
+            .and_time(NaiveTime::MIN);
+        let next_day = day + Duration::days(1);
+
+        match op {
+            "<" => self.due_before = Some(day),
+            "<=" => self.due_before = Some(next_day),
+            ">" => self.due_after = Some(next_day),
+            ">=" => self.due_after = Some(day),
+            _ => {
+                self.due_after = Some(day);
+                self.due_before = Some(next_day);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds tags from a filter like `#work -#later`, a leading `-` excluding
+    /// the tag.
+    pub fn filter_tags(&mut self, input: &str) -> Result<()> {
+        for word in input.split_whitespace() {
+            match word.strip_prefix('-') {
+                Some(tag) => self.without_tags.push(parse_tag(tag)?),
+                None => self.tags.push(parse_tag(word)?),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sorts by [`TaskQuery::search`] score and [`TaskQuery::sort`], then
+    /// by creation date and id.
+    pub fn sort(&self, tasks: &mut [(TaskId, Task)]) {
+        tasks.sort_unstable_by(|(id1, task1), (id2, task2)| {
+            let score = self.search_score(task2).cmp(&self.search_score(task1));
+            let order = match self.sort {
+                Sort::Creation => Ordering::Equal,
+                Sort::Due => task1.preferences.expire.cmp(&task2.preferences.expire),
+                Sort::Priority => task2.priority.cmp(&task1.priority),
+                Sort::Title => task1.title.to_lowercase().cmp(&task2.title.to_lowercase()),
+                Sort::DoneLast => task1.done.cmp(&task2.done),
+            };
+
+            score
+                .then(order)
+                .then(task1.creation_date.cmp(&task2.creation_date))
+                .then(id1.cmp(id2))
+        });
+    }
+}
+
+/// How well `pattern` matches `text` as a case-insensitive subsequence,
+/// higher being better, or `None` if it doesn't. Consecutive characters and
+/// ones starting a word score more; spaces in `pattern` are ignored.
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<u32> {
+    let text = text.to_lowercase().chars().collect::<Vec<_>>();
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+
+    for c in pattern.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let start = previous.map_or(0, |i| i + 1);
+        let index = start + text[start..].iter().position(|t| *t == c)?;
+
+        score += 1;
+
+        if previous.is_some_and(|i| i + 1 == index) {
+            score += 4;
+        }
+
+        if index == 0 || !text[index - 1].is_alphanumeric() {
+            score += 2;
+        }
+
+        previous = Some(index);
+    }
+
+    Some(score)
+}
+
+/// Splits a query into words and `"quoted text"`, marking the latter.
+fn terms(input: &str) -> Result<Vec<(String, bool)>> {
+    let mut terms = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '"' => {
+                let mut text = String::new();
+                let mut closed = false;
+
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        closed = true;
+                        break;
+                    }
+
+                    text.push(c);
+                }
+
+                if !closed {
+                    bail!("Missing closing quote")
+                }
+
+                terms.push((text, true));
+            }
+            c => {
+                let mut word = c.to_string();
+
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    word.push(c);
+                }
+
+                terms.push((word, false));
+            }
+        }
+    }
+
+    Ok(terms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_tags() {
+        let mut query = TaskQuery::default();
+        query.filter_tags("#work -#later Home").unwrap();
+
+        assert_eq!(query.tags, vec!["work", "home"]);
+        assert_eq!(query.without_tags, vec!["later"]);
+        assert!(query.filter_tags("#").is_err());
+
+        let task = Task {
+            tags: crate::parse_tags("work home").unwrap(),
+            ..Default::default()
+        };
+        assert!(query.matches(&task, NaiveDateTime::default()));
+
+        query.without_tags.push("home".to_string());
+        assert!(!query.matches(&task, NaiveDateTime::default()));
+    }
+
+    #[test]
+    fn sorts_by_priority() {
+        let task = |priority, done| Task {
+            priority,
+            done,
+            ..Default::default()
+        };
+        let mut tasks = vec![
+            ("a".to_string(), task(crate::Priority::Low, false)),
+            ("b".to_string(), task(crate::Priority::Urgent, true)),
+            ("c".to_string(), task(crate::Priority::None, false)),
+        ];
+        let ids = |tasks: &[(TaskId, Task)]| {
+            tasks.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>()
+        };
+
+        let mut query = TaskQuery {
+            sort: Sort::Priority,
+            ..Default::default()
+        };
+        query.sort(&mut tasks);
+        assert_eq!(ids(&tasks), vec!["b", "a", "c"]);
+
+        query.sort = query.sort.next().next();
+        assert_eq!(query.sort, Sort::DoneLast);
+        query.sort(&mut tasks);
+        assert_eq!(ids(&tasks), vec!["a", "c", "b"]);
+
+        assert_eq!(query.sort.next(), Sort::Creation);
+        assert_eq!("done-last".parse::<Sort>().unwrap(), Sort::DoneLast);
+    }
+
+    #[test]
+    fn parses_query_language() {
+        let mut query = TaskQuery::default();
+        query
+            .filter(r#"done:no due:<2026-11-01 tag:work -#later "call mom" plnts"#)
+            .unwrap();
+
+        let date = |day| {
+            NaiveDate::from_ymd_opt(2026, 11, day)
+                .unwrap()
+                .and_time(NaiveTime::MIN)
+        };
+
+        assert_eq!(query.done, Some(false));
+        assert_eq!(query.due_before, Some(date(1)));
+        assert_eq!(query.tags, vec!["work"]);
+        assert_eq!(query.without_tags, vec!["later"]);
+        assert_eq!(query.text.as_deref(), Some("call mom"));
+        assert_eq!(query.search.as_deref(), Some("plnts"));
+
+        let mut query = TaskQuery::default();
+        query.filter("due:2026-11-02").unwrap();
+        assert_eq!((query.due_after, query.due_before), (Some(date(2)), Some(date(3))));
+
+        assert!(TaskQuery::default().filter("done:maybe").is_err());
+        assert!(TaskQuery::default().filter("due:<soon").is_err());
+        assert!(TaskQuery::default().filter("\"call mom").is_err());
+    }
+
+    #[test]
+    fn ranks_fuzzy_matches() {
+        assert!(fuzzy_score("wtr", "Water the plants").is_some());
+        assert!(fuzzy_score("wtrx", "Water the plants").is_none());
+        assert!(fuzzy_score("pla", "Water the plants") > fuzzy_score("pla", "Repair a lamp"));
+
+        let task = |title: &str| Task {
+            title: title.to_string(),
+            ..Default::default()
+        };
+        let mut tasks = vec![
+            ("a".to_string(), task("Repair a lamp")),
+            ("b".to_string(), task("Call mom")),
+            ("c".to_string(), task("Water the plants")),
+        ];
+        let query = TaskQuery {
+            search: Some("pla".to_string()),
+            ..Default::default()
+        };
+
+        tasks.retain(|(_, task)| query.matches(task, NaiveDateTime::default()));
+        query.sort(&mut tasks);
+        assert_eq!(tasks[0].0, "c");
+        assert_eq!(tasks.len(), 2);
+    }
+}
@@ -0,0 +1,277 @@
+use anyhow::{bail, Context, Error, Result};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Largest `INTERVAL` accepted. Longer ones would overflow dates, so rules
+/// read from elsewhere are clamped to it too.
+const MAX_INTERVAL: u32 = 1000;
+
+/// How often a task comes back, a subset of RFC 5545 RRULE:
+/// `FREQ` of `DAILY`, `WEEKLY` or `MONTHLY` with `INTERVAL`,
+/// `BYDAY` (weekly) and `BYMONTHDAY` (monthly).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Recurrence {
+    /// Every `n` days.
+    Days(u32),
+    /// Every `interval` weeks on the given weekdays,
+    /// or on the weekday of the previous deadline if empty.
+    Weekly { interval: u32, weekdays: Vec<Weekday> },
+    /// Every `interval` months on `day`, clamped to the length of the month.
+    Monthly { interval: u32, day: u32 },
+}
+
+impl Recurrence {
+    /// First occurrence strictly after `date`, keeping its time of day.
+    pub fn next_after(&self, date: NaiveDateTime) -> NaiveDateTime {
+        let day = date.date();
+
+        let next = match self {
+            Recurrence::Days(n) => day + Duration::days((*n).clamp(1, MAX_INTERVAL) as i64),
+            Recurrence::Weekly { interval, weekdays } => {
+                let monday =
+                    |d: NaiveDate| d - Duration::days(d.weekday().num_days_from_monday() as i64);
+                let interval = (*interval).clamp(1, MAX_INTERVAL) as i64;
+
+                (1..)
+                    .map(|i| day + Duration::days(i))
+                    .find(|d| {
+                        let on_weekday = if weekdays.is_empty() {
+                            d.weekday() == day.weekday()
+                        } else {
+                            weekdays.contains(&d.weekday())
+                        };
+
+                        on_weekday && (monday(*d) - monday(day)).num_days() / 7 % interval == 0
+                    })
+                    .unwrap()
+            }
+            Recurrence::Monthly { interval, day: month_day } => {
+                let this_month = in_month(day.year(), day.month(), *month_day);
+
+                if this_month > day {
+                    this_month
+                } else {
+                    let months = day.month0() + (*interval).clamp(1, MAX_INTERVAL);
+
+                    in_month(
+                        day.year() + (months / 12) as i32,
+                        months % 12 + 1,
+                        *month_day,
+                    )
+                }
+            }
+        };
+
+        next.and_time(date.time())
+    }
+
+    /// Shorthands used by the preferences window: `daily`, `weekly` and
+    /// `monthly` repeat on the weekday or day of `expire`, anything else
+    /// is parsed as an RRULE.
+    pub fn parse(input: &str, expire: NaiveDateTime) -> Result<Self> {
+        match input.trim().to_lowercase().as_str() {
+            "daily" => Ok(Recurrence::Days(1)),
+            "weekly" => Ok(Recurrence::Weekly {
+                interval: 1,
+                weekdays: vec![expire.weekday()],
+            }),
+            "monthly" => Ok(Recurrence::Monthly {
+                interval: 1,
+                day: expire.day(),
+            }),
+            _ => input.parse(),
+        }
+    }
+}
+
+fn in_month(year: i32, month: u32, day: u32) -> NaiveDate {
+    (1..=day.clamp(1, 31))
+        .rev()
+        .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+        .unwrap()
+}
+
+impl Display for Recurrence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Recurrence::Days(n) => write!(f, "FREQ=DAILY;INTERVAL={}", n),
+            Recurrence::Weekly { interval, weekdays } => {
+                write!(f, "FREQ=WEEKLY;INTERVAL={}", interval)?;
+
+                if !weekdays.is_empty() {
+                    let days = weekdays.iter().map(|d| ical_weekday(*d)).collect::<Vec<_>>();
+
+                    write!(f, ";BYDAY={}", days.join(","))?;
+                }
+
+                Ok(())
+            }
+            Recurrence::Monthly { interval, day } => {
+                write!(f, "FREQ=MONTHLY;INTERVAL={};BYMONTHDAY={}", interval, day)
+            }
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let s = s.strip_prefix("RRULE:").unwrap_or(s);
+
+        let mut freq = None;
+        let mut interval = 1;
+        let mut weekdays = Vec::new();
+        let mut month_day = None;
+
+        for part in s.split(';').filter(|p| !p.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .with_context(|| format!("Invalid rule part: {}", part))?;
+
+            match name.to_uppercase().as_str() {
+                "FREQ" => freq = Some(value.to_uppercase()),
+                "INTERVAL" => {
+                    interval = value
+                        .parse()
+                        .with_context(|| format!("Invalid interval: {}", value))?
+                }
+                "BYDAY" => {
+                    weekdays = value
+                        .split(',')
+                        .map(parse_weekday)
+                        .collect::<Result<_>>()?
+                }
+                "BYMONTHDAY" => {
+                    month_day = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("Invalid month day: {}", value))?,
+                    )
+                }
+                _ => bail!("Unsupported rule part: {}", name),
+            }
+        }
+
+        if !(1..=MAX_INTERVAL).contains(&interval) {
+            bail!("Interval must be between 1 and {}", MAX_INTERVAL)
+        }
+
+        match freq.as_deref() {
+            Some("DAILY") => Ok(Recurrence::Days(interval)),
+            Some("WEEKLY") => Ok(Recurrence::Weekly { interval, weekdays }),
+            Some("MONTHLY") => Ok(Recurrence::Monthly {
+                interval,
+                day: month_day.with_context(|| "Monthly rule needs BYMONTHDAY")?,
+            }),
+            Some(freq) => bail!("Unsupported frequency: {}", freq),
+            None => bail!("Rule needs FREQ"),
+        }
+    }
+}
+
+fn ical_weekday(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn parse_weekday(day: &str) -> Result<Weekday> {
+    match day.trim().to_uppercase().as_str() {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => bail!("Invalid weekday: {}", day),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(9, 30, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn every_n_days() {
+        let rule = Recurrence::Days(3);
+
+        assert_eq!(rule.next_after(date(2022, 12, 30)), date(2023, 1, 2));
+    }
+
+    #[test]
+    fn weekly_on_weekdays() {
+        // 2022-09-12 is a Monday
+        let rule: Recurrence = "FREQ=WEEKLY;BYDAY=MO,TH".parse().unwrap();
+
+        assert_eq!(rule.next_after(date(2022, 9, 12)), date(2022, 9, 15));
+        assert_eq!(rule.next_after(date(2022, 9, 15)), date(2022, 9, 19));
+
+        let rule: Recurrence = "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH".parse().unwrap();
+
+        assert_eq!(rule.next_after(date(2022, 9, 15)), date(2022, 9, 26));
+    }
+
+    #[test]
+    fn monthly_clamps_day() {
+        let rule: Recurrence = "RRULE:FREQ=MONTHLY;BYMONTHDAY=31".parse().unwrap();
+
+        assert_eq!(rule.next_after(date(2022, 1, 31)), date(2022, 2, 28));
+        assert_eq!(rule.next_after(date(2022, 2, 28)), date(2022, 3, 31));
+
+        let rule = Recurrence::Monthly { interval: 3, day: 15 };
+
+        assert_eq!(rule.next_after(date(2022, 11, 20)), date(2023, 2, 15));
+    }
+
+    #[test]
+    fn rrule_round_trip() {
+        for rule in [
+            "FREQ=DAILY;INTERVAL=4",
+            "FREQ=WEEKLY;INTERVAL=1;BYDAY=TU,SA",
+            "FREQ=MONTHLY;INTERVAL=2;BYMONTHDAY=5",
+        ] {
+            assert_eq!(rule.parse::<Recurrence>().unwrap().to_string(), rule);
+        }
+
+        assert!("FREQ=YEARLY".parse::<Recurrence>().is_err());
+        assert!("FREQ=DAILY;INTERVAL=0".parse::<Recurrence>().is_err());
+    }
+
+    #[test]
+    fn rejects_huge_intervals() {
+        assert!("FREQ=DAILY;INTERVAL=4294967295".parse::<Recurrence>().is_err());
+
+        // Rules that skipped parsing, like imported JSON, are clamped
+        for rule in [
+            Recurrence::Days(u32::MAX),
+            Recurrence::Weekly {
+                interval: u32::MAX,
+                weekdays: Vec::new(),
+            },
+            Recurrence::Monthly {
+                interval: u32::MAX,
+                day: 1,
+            },
+        ] {
+            assert!(rule.next_after(date(2022, 9, 12)) > date(2022, 9, 12));
+        }
+    }
+}
@@ -0,0 +1,533 @@
+//! Tasks in an SQLite database, behind the `sqlite` feature.
+//!
+//! Each row holds the whole task as JSON next to the columns that
+//! [`TaskQuery`] filters and sorts on, so queries run in SQL on indexes
+//! instead of decoding every task. Only the fuzzy [`TaskQuery::search`]
+//! is applied to the rows SQL returns. Text is compared through
+//! `unicode_lower`, as SQLite's `lower` only knows ASCII.
+
+use crate::query::Sort;
+use crate::{Task, TaskId, TaskQuery, TaskState, TaskStore};
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use std::path::Path;
+
+/// Sorts like the text, fractions of a second only when present.
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS tasks (
+    id TEXT PRIMARY KEY NOT NULL,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    done INTEGER NOT NULL,
+    creation_date TEXT NOT NULL,
+    expire TEXT NOT NULL,
+    archived TEXT,
+    task TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS tasks_expire ON tasks (expire);
+CREATE INDEX IF NOT EXISTS tasks_done ON tasks (archived, done);
+CREATE INDEX IF NOT EXISTS tasks_creation_date ON tasks (creation_date);
+CREATE TABLE IF NOT EXISTS task_tags (
+    task_id TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (task_id, tag)
+);
+CREATE INDEX IF NOT EXISTS task_tags_tag ON task_tags (tag);
+";
+
+/// Columns added after the first version of the table, with their type and
+/// index. Rows written before hold `NULL`, which matches the task they hold.
+const COLUMNS: [(&str, &str, &str); 2] = [
+    (
+        "project",
+        "TEXT",
+        "CREATE INDEX IF NOT EXISTS tasks_project ON tasks (project)",
+    ),
+    (
+        "priority",
+        "INTEGER",
+        "CREATE INDEX IF NOT EXISTS tasks_priority ON tasks (priority)",
+    ),
+];
+
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed open SQLite database at {}", path.display()))?;
+
+        Self::load(connection)
+    }
+
+    /// An empty database which is removed when dropped.
+    pub fn memory() -> Result<Self> {
+        Self::load(Connection::open_in_memory()?)
+    }
+
+    fn load(connection: Connection) -> Result<Self> {
+        connection.create_scalar_function(
+            "unicode_lower",
+            1,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |context| Ok(context.get::<String>(0)?.to_lowercase()),
+        )?;
+        connection.execute_batch(SCHEMA)?;
+
+        for (column, kind, index) in COLUMNS {
+            let exists = connection
+                .prepare("SELECT 1 FROM pragma_table_info('tasks') WHERE name = ?1")?
+                .exists([column])?;
+
+            if !exists {
+                connection.execute_batch(&format!(
+                    "ALTER TABLE tasks ADD COLUMN {} {}",
+                    column, kind
+                ))?;
+            }
+
+            connection.execute_batch(index)?;
+        }
+
+        Ok(Self { connection })
+    }
+
+    fn select(&self, filter: &str, values: Vec<Value>, order: &str) -> Result<Vec<(TaskId, Task)>> {
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT id, task FROM tasks {} ORDER BY {}",
+            filter, order
+        ))?;
+
+        let rows = statement
+            .query_map(params_from_iter(values), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        rows.into_iter()
+            .map(|(id, data)| {
+                let task = decode(&id, &data)?;
+                Ok((id, task))
+            })
+            .collect()
+    }
+}
+
+impl TaskStore for SqliteStore {
+    fn get(&self, id: &str) -> Result<Option<Task>> {
+        self.connection
+            .query_row("SELECT task FROM tasks WHERE id = ?1", [id], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()?
+            .map(|data| decode(id, &data))
+            .transpose()
+    }
+
+    fn insert(&self, id: &str, task: &Task) -> Result<()> {
+        let transaction = self.connection.unchecked_transaction()?;
+
+        transaction.execute(
+            "INSERT OR REPLACE INTO tasks
+                (id, title, description, done, creation_date, expire, archived, task, project,
+                    priority)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                id,
+                task.title,
+                task.description,
+                task.done,
+                format_date(task.creation_date),
+                format_date(task.preferences.expire),
+                task.archived.map(format_date),
+                serde_json::to_string(task)?,
+                task.project,
+                task.priority as u8,
+            ],
+        )?;
+        transaction.execute("DELETE FROM task_tags WHERE task_id = ?1", [id])?;
+
+        for tag in &task.tags {
+            transaction.execute(
+                "INSERT INTO task_tags (task_id, tag) VALUES (?1, ?2)",
+                [id, tag],
+            )?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        let transaction = self.connection.unchecked_transaction()?;
+
+        transaction.execute("DELETE FROM tasks WHERE id = ?1", [id])?;
+        transaction.execute("DELETE FROM task_tags WHERE task_id = ?1", [id])?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<(TaskId, Task)>> {
+        self.select("", Vec::new(), "id")
+    }
+
+    fn contains(&self, id: &str) -> Result<bool> {
+        Ok(self
+            .connection
+            .query_row("SELECT 1 FROM tasks WHERE id = ?1", [id], |_| Ok(()))
+            .optional()?
+            .is_some())
+    }
+
+    fn query(&self, query: &TaskQuery, now: NaiveDateTime) -> Result<Vec<(TaskId, Task)>> {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+
+        if !query.states.is_empty() {
+            let now = if query
+                .states
+                .iter()
+                .any(|s| matches!(s, TaskState::Active | TaskState::Overdue))
+            {
+                values.push(Value::Text(format_date(now)));
+                values.len()
+            } else {
+                0
+            };
+
+            let states = query
+                .states
+                .iter()
+                .map(|state| match state {
+                    TaskState::Archived => "archived IS NOT NULL".to_string(),
+                    TaskState::Completed => "(archived IS NULL AND done)".to_string(),
+                    TaskState::Overdue => {
+                        format!("(archived IS NULL AND NOT done AND expire <= ?{})", now)
+                    }
+                    TaskState::Active => {
+                        format!("(archived IS NULL AND NOT done AND expire > ?{})", now)
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            conditions.push(format!("({})", states.join(" OR ")));
+        }
+
+        if let Some(done) = query.done {
+            values.push(Value::Integer(done.into()));
+            conditions.push(format!("done = ?{}", values.len()));
+        }
+
+        if let Some(date) = query.due_before {
+            values.push(Value::Text(format_date(date)));
+            conditions.push(format!("expire < ?{}", values.len()));
+        }
+
+        if let Some(date) = query.due_after {
+            values.push(Value::Text(format_date(date)));
+            conditions.push(format!("expire >= ?{}", values.len()));
+        }
+
+        if let Some(project) = &query.project {
+            values.push(Value::Text(project.clone()));
+            conditions.push(format!("project = ?{}", values.len()));
+        }
+
+        for tag in &query.tags {
+            values.push(Value::Text(tag.clone()));
+            conditions.push(format!(
+                "id IN (SELECT task_id FROM task_tags WHERE tag = ?{})",
+                values.len()
+            ));
+        }
+
+        for tag in &query.without_tags {
+            values.push(Value::Text(tag.clone()));
+            conditions.push(format!(
+                "id NOT IN (SELECT task_id FROM task_tags WHERE tag = ?{})",
+                values.len()
+            ));
+        }
+
+        if let Some(text) = &query.text {
+            values.push(Value::Text(text.to_lowercase()));
+            conditions.push(format!(
+                "(instr(unicode_lower(title), ?{0}) OR instr(unicode_lower(description), ?{0}))",
+                values.len()
+            ));
+        }
+
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        let order = match query.sort {
+            Sort::Creation => "creation_date, id",
+            Sort::Due => "expire, creation_date, id",
+            Sort::Priority => "ifnull(priority, 0) DESC, creation_date, id",
+            Sort::Title => "unicode_lower(title), creation_date, id",
+            Sort::DoneLast => "done, creation_date, id",
+        };
+
+        let mut tasks = self.select(&filter, values, order)?;
+
+        if query.search.is_some() {
+            tasks.retain(|(_, task)| query.search_score(task).is_some());
+            query.sort(&mut tasks);
+        }
+
+        Ok(tasks)
+    }
+}
+
+fn format_date(date: NaiveDateTime) -> String {
+    date.format(DATE_FORMAT).to_string()
+}
+
+fn decode(id: &str, data: &str) -> Result<Task> {
+    serde_json::from_str(data).with_context(|| format!("Failed decode task {}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryStore, Preferences, Priority};
+    use chrono::{Duration, NaiveDate};
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2022, 9, 10)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    fn fill(store: &dyn TaskStore) {
+        let tasks = [
+            ("a", "water plants", 2, false, false, Some("home"), "garden", Priority::Low),
+            ("b", "Call mom", -1, false, false, None, "phone family", Priority::Urgent),
+            ("c", "buy milk", 1, true, false, Some("home"), "errand", Priority::None),
+            ("d", "Old report", -5, true, true, Some("work"), "", Priority::Urgent),
+            ("e", "Plan trip", 3, false, false, None, "family later", Priority::None),
+        ];
+
+        for (i, (id, title, days, done, archived, project, tags, priority)) in
+            tasks.into_iter().enumerate()
+        {
+            let task = Task {
+                title: title.to_string(),
+                project: project.map(str::to_string),
+                tags: crate::parse_tags(tags).unwrap(),
+                priority,
+                done,
+                creation_date: now() - Duration::hours(i as i64),
+                archived: archived.then(now),
+                preferences: Preferences {
+                    expire: now() + Duration::days(days),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            store.insert(id, &task).unwrap();
+        }
+    }
+
+    fn ids(store: &dyn TaskStore, query: TaskQuery) -> Vec<TaskId> {
+        store
+            .query(&query, now())
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn queries_match_memory_store() {
+        let sqlite = SqliteStore::memory().unwrap();
+        let memory = MemoryStore::default();
+        fill(&sqlite);
+        fill(&memory);
+
+        let queries = [
+            TaskQuery::default(),
+            TaskQuery {
+                states: vec![TaskState::Active, TaskState::Completed],
+                ..Default::default()
+            },
+            TaskQuery {
+                states: vec![TaskState::Overdue],
+                ..Default::default()
+            },
+            TaskQuery {
+                states: vec![TaskState::Archived],
+                sort: Sort::Title,
+                ..Default::default()
+            },
+            TaskQuery {
+                done: Some(false),
+                due_before: Some(now() + Duration::days(3)),
+                sort: Sort::Due,
+                ..Default::default()
+            },
+            TaskQuery {
+                text: Some("PL".to_string()),
+                sort: Sort::Title,
+                ..Default::default()
+            },
+            TaskQuery {
+                project: Some("home".to_string()),
+                ..Default::default()
+            },
+            TaskQuery {
+                sort: Sort::Priority,
+                ..Default::default()
+            },
+            TaskQuery {
+                search: Some("pl".to_string()),
+                sort: Sort::Due,
+                ..Default::default()
+            },
+            TaskQuery {
+                sort: Sort::DoneLast,
+                ..Default::default()
+            },
+            TaskQuery {
+                tags: vec!["family".to_string()],
+                without_tags: vec!["later".to_string()],
+                ..Default::default()
+            },
+        ];
+
+        for query in queries {
+            assert_eq!(
+                ids(&sqlite, query.clone()),
+                ids(&memory, query.clone()),
+                "{:?}",
+                query
+            );
+        }
+
+        assert_eq!(
+            ids(&sqlite, TaskQuery::default()),
+            vec!["e", "d", "c", "b", "a"]
+        );
+        assert_eq!(
+            ids(
+                &sqlite,
+                TaskQuery {
+                    states: vec![TaskState::Overdue],
+                    ..Default::default()
+                }
+            ),
+            vec!["b"]
+        );
+    }
+
+    #[test]
+    fn lowercases_unicode() {
+        let sqlite = SqliteStore::memory().unwrap();
+        let memory = MemoryStore::default();
+
+        for store in [&sqlite as &dyn TaskStore, &memory] {
+            for (id, title) in [("a", "Ąę"), ("b", "ąb"), ("c", "Ab")] {
+                let task = Task {
+                    title: title.to_string(),
+                    ..Default::default()
+                };
+
+                store.insert(id, &task).unwrap();
+            }
+        }
+
+        let search = TaskQuery {
+            text: Some("ąę".to_string()),
+            ..Default::default()
+        };
+        let by_title = TaskQuery {
+            sort: Sort::Title,
+            ..Default::default()
+        };
+
+        assert_eq!(ids(&sqlite, search.clone()), vec!["a"]);
+        assert_eq!(ids(&sqlite, search.clone()), ids(&memory, search));
+        assert_eq!(ids(&sqlite, by_title.clone()), vec!["c", "b", "a"]);
+        assert_eq!(ids(&sqlite, by_title.clone()), ids(&memory, by_title));
+    }
+
+    #[test]
+    fn adds_new_columns() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE tasks (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    title TEXT NOT NULL,
+                    description TEXT NOT NULL,
+                    done INTEGER NOT NULL,
+                    creation_date TEXT NOT NULL,
+                    expire TEXT NOT NULL,
+                    archived TEXT,
+                    task TEXT NOT NULL
+                )",
+            )
+            .unwrap();
+
+        let store = SqliteStore::load(connection).unwrap();
+        fill(&store);
+
+        let query = TaskQuery {
+            project: Some("work".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(&store, query), vec!["d"]);
+
+        let query = TaskQuery {
+            sort: Sort::Priority,
+            ..Default::default()
+        };
+        assert_eq!(ids(&store, query), vec!["d", "b", "a", "e", "c"]);
+    }
+
+    #[test]
+    fn tags_follow_tasks() {
+        let store = SqliteStore::memory().unwrap();
+        fill(&store);
+
+        let family = TaskQuery {
+            tags: vec!["family".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(ids(&store, family.clone()), vec!["e", "b"]);
+
+        store.insert("e", &Task::default()).unwrap();
+        store.remove("b").unwrap();
+        assert!(ids(&store, family).is_empty());
+    }
+
+    #[test]
+    fn uses_indexes() {
+        let store = SqliteStore::memory().unwrap();
+
+        let plan = store
+            .connection
+            .prepare("EXPLAIN QUERY PLAN SELECT id FROM tasks WHERE expire < ?1")
+            .unwrap()
+            .query_map(["2022-09-10"], |row| row.get::<_, String>(3))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+
+        assert!(plan.iter().any(|step| step.contains("tasks_expire")));
+    }
+}
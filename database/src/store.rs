@@ -0,0 +1,242 @@
+use crate::crypto::Cipher;
+use crate::{Task, TaskId, TaskQuery};
+use anyhow::{Context, Result};
+use bincode::config::Configuration;
+use chrono::NaiveDateTime;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sled::Tree;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Where the tasks of a [`Database`](crate::Database) are kept.
+///
+/// The account, the schema version and sync states always stay in sled.
+pub trait TaskStore: Send {
+    fn get(&self, id: &str) -> Result<Option<Task>>;
+
+    /// Stores a task, replacing any task with that id.
+    fn insert(&self, id: &str, task: &Task) -> Result<()>;
+
+    fn remove(&self, id: &str) -> Result<()>;
+
+    fn list(&self) -> Result<Vec<(TaskId, Task)>>;
+
+    fn contains(&self, id: &str) -> Result<bool> {
+        Ok(self.get(id)?.is_some())
+    }
+
+    /// Tasks matching `query`, in its order. Stores that can filter and
+    /// sort by themselves should override this.
+    fn query(&self, query: &TaskQuery, now: NaiveDateTime) -> Result<Vec<(TaskId, Task)>> {
+        let mut tasks = self
+            .list()?
+            .into_iter()
+            .filter(|(_, task)| query.matches(task, now))
+            .collect::<Vec<_>>();
+
+        query.sort(&mut tasks);
+
+        Ok(tasks)
+    }
+
+    /// Whether tasks are encrypted together with the database.
+    fn encrypted(&self) -> bool {
+        false
+    }
+}
+
+/// Keeps tasks in memory only, for tests and throwaway lists.
+#[derive(Default)]
+pub struct MemoryStore {
+    tasks: Mutex<BTreeMap<TaskId, Task>>,
+}
+
+impl TaskStore for MemoryStore {
+    fn get(&self, id: &str) -> Result<Option<Task>> {
+        Ok(self.tasks.lock().unwrap().get(id).cloned())
+    }
+
+    fn insert(&self, id: &str, task: &Task) -> Result<()> {
+        self.tasks
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), task.clone());
+
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        self.tasks.lock().unwrap().remove(id);
+
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<(TaskId, Task)>> {
+        Ok(self
+            .tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, task)| (id.clone(), task.clone()))
+            .collect())
+    }
+}
+
+/// Encodes sled values with bincode, encrypting them once a key is set.
+/// Clones share the key.
+#[derive(Clone)]
+pub(crate) struct Codec {
+    config: Configuration,
+    cipher: Arc<RwLock<Option<Cipher>>>,
+}
+
+impl Codec {
+    pub(crate) fn new(config: Configuration) -> Self {
+        Self {
+            config,
+            cipher: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub(crate) fn set_cipher(&self, cipher: Option<Cipher>) {
+        *self.cipher.write().unwrap() = cipher;
+    }
+
+    pub(crate) fn seal(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        match &*self.cipher.read().unwrap() {
+            Some(cipher) => cipher.encrypt(&data),
+            None => Ok(data),
+        }
+    }
+
+    pub(crate) fn unseal(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match &*self.cipher.read().unwrap() {
+            Some(cipher) => cipher.decrypt(data),
+            None => Ok(data.to_vec()),
+        }
+    }
+
+    pub(crate) fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        self.seal(bincode::serde::encode_to_vec(value, self.config)?)
+    }
+
+    pub(crate) fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        let data = self.unseal(data)?;
+
+        Ok(bincode::serde::decode_from_slice(&data, self.config)?.0)
+    }
+}
+
+/// The `tasks` tree of the database, the default store.
+pub(crate) struct SledStore {
+    tree: Tree,
+    codec: Codec,
+}
+
+impl SledStore {
+    pub(crate) fn new(tree: Tree, codec: Codec) -> Self {
+        Self { tree, codec }
+    }
+}
+
+impl TaskStore for SledStore {
+    fn get(&self, id: &str) -> Result<Option<Task>> {
+        match self.tree.get(id)? {
+            Some(data) => Ok(Some(
+                self.codec
+                    .decode(&data)
+                    .with_context(|| format!("Failed decode task {}", id))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&self, id: &str, task: &Task) -> Result<()> {
+        self.tree.insert(id, self.codec.encode(task)?)?;
+
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        self.tree.remove(id)?;
+
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<(TaskId, Task)>> {
+        self.tree
+            .iter()
+            .map(|v| {
+                let (id, data) = v?;
+                let id = String::from_utf8(id.to_vec())?;
+                let task = self
+                    .codec
+                    .decode(&data)
+                    .with_context(|| format!("Failed decode task {}", id))?;
+
+                Ok((id, task))
+            })
+            .collect()
+    }
+
+    fn contains(&self, id: &str) -> Result<bool> {
+        Ok(self.tree.contains_key(id)?)
+    }
+
+    fn encrypted(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileStore;
+
+    /// Behaviour every store must share.
+    fn check(store: &dyn TaskStore) {
+        let task = Task {
+            title: "Water".to_string(),
+            ..Default::default()
+        };
+
+        assert!(store.get("a").unwrap().is_none());
+        assert!(!store.contains("a").unwrap());
+
+        store.insert("a", &task).unwrap();
+        store.insert("b", &task).unwrap();
+        assert!(store.contains("a").unwrap());
+        assert_eq!(store.get("a").unwrap().unwrap().title, "Water");
+
+        let renamed = Task {
+            title: "Watered".to_string(),
+            ..Default::default()
+        };
+        store.insert("a", &renamed).unwrap();
+        assert_eq!(store.get("a").unwrap().unwrap().title, "Watered");
+
+        store.remove("b").unwrap();
+        store.remove("missing").unwrap();
+
+        let ids = store
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["a"]);
+    }
+
+    #[test]
+    fn stores_behave_alike() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let codec = Codec::new(bincode::config::standard());
+        check(&SledStore::new(db.open_tree("tasks").unwrap(), codec));
+
+        check(&MemoryStore::default());
+
+        let dir = tempfile::tempdir().unwrap();
+        check(&FileStore::open(dir.path(), false).unwrap());
+    }
+}
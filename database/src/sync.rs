@@ -0,0 +1,49 @@
+use crate::{Database, TaskId};
+use anyhow::Result;
+use sled::Tree;
+
+/// What a sync target looked like after the last sync, per task id.
+///
+/// Each target (a todo.txt file, a calendar) gets its own namespace. Values
+/// are opaque to the database, e.g. a content hash or an ETag, and are
+/// stored unencrypted.
+pub struct SyncState {
+    tree: Tree,
+}
+
+impl SyncState {
+    pub fn get(&self, id: &str) -> Result<Option<String>> {
+        Ok(self
+            .tree
+            .get(id)?
+            .map(|v| String::from_utf8_lossy(&v).to_string()))
+    }
+
+    pub fn set(&self, id: &str, value: &str) -> Result<()> {
+        self.tree.insert(id, value.as_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn remove(&self, id: &str) -> Result<()> {
+        self.tree.remove(id)?;
+
+        Ok(())
+    }
+
+    pub fn ids(&self) -> Result<Vec<TaskId>> {
+        self.tree
+            .iter()
+            .keys()
+            .map(|k| Ok(String::from_utf8(k?.to_vec())?))
+            .collect()
+    }
+}
+
+impl Database {
+    pub fn sync_state(&self, namespace: &str) -> Result<SyncState> {
+        Ok(SyncState {
+            tree: self.db.open_tree(format!("sync:{}", namespace))?,
+        })
+    }
+}
@@ -0,0 +1,233 @@
+use crate::projects::ProjectId;
+use crate::recurrence::Recurrence;
+use anyhow::{bail, Error, Result};
+use chrono::{Datelike, Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+pub type TaskId = String;
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct Task {
+    pub title: String,
+    pub description: String,
+    pub done: bool,
+    pub exp_added: bool,
+    pub creation_date: NaiveDateTime,
+    pub preferences: Preferences,
+    /// When the task was marked done, one entry per period for repeating tasks.
+    pub completions: Vec<NaiveDateTime>,
+    /// When the task was moved to the archive.
+    pub archived: Option<NaiveDateTime>,
+    pub subtasks: Vec<Subtask>,
+    pub project: Option<ProjectId>,
+    /// Lowercase words, see [`parse_tag`].
+    pub tags: BTreeSet<String>,
+    pub priority: Priority,
+    /// Tasks to be done before this one, see [`Database::add_dependency`](crate::Database::add_dependency).
+    pub depends_on: BTreeSet<TaskId>,
+}
+
+/// How urgent a task is, lowest first.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    #[default]
+    None,
+    Low,
+    Medium,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 5] = [
+        Priority::None,
+        Priority::Low,
+        Priority::Medium,
+        Priority::High,
+        Priority::Urgent,
+    ];
+
+    /// Percent of the EXP awarded for a task of this priority when
+    /// multipliers are on, see [`Database::set_exp_multipliers`](crate::Database::set_exp_multipliers).
+    pub fn exp_multiplier(self) -> u32 {
+        match self {
+            Priority::None | Priority::Low => 100,
+            Priority::Medium => 125,
+            Priority::High => 150,
+            Priority::Urgent => 200,
+        }
+    }
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Priority::None => "none",
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Priority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_lowercase();
+
+        match Priority::ALL.iter().find(|p| p.to_string() == s) {
+            Some(priority) => Ok(*priority),
+            None if s.is_empty() => Ok(Priority::None),
+            None => bail!("Invalid priority: {}, expected none, low, medium, high or urgent", s),
+        }
+    }
+}
+
+/// A checklist item of a task.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct Subtask {
+    pub title: String,
+    pub done: bool,
+    /// Experience awarded the first time the item is done.
+    pub exp: u32,
+    pub exp_added: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskState {
+    Active,
+    Overdue,
+    Completed,
+    Archived,
+}
+
+impl Task {
+    pub fn state(&self, now: NaiveDateTime) -> TaskState {
+        if self.archived.is_some() {
+            TaskState::Archived
+        } else if self.done {
+            TaskState::Completed
+        } else if self.preferences.expire <= now {
+            TaskState::Overdue
+        } else {
+            TaskState::Active
+        }
+    }
+
+    pub fn set_done(&mut self, done: bool, now: NaiveDateTime) {
+        if done == self.done {
+            return;
+        }
+
+        self.done = done;
+
+        if done {
+            self.completions.push(now);
+        } else {
+            self.completions.pop();
+        }
+    }
+
+    /// Moves a repeating task whose deadline has passed to its next period,
+    /// clearing `done` and `exp_added`. Returns whether the task changed.
+    pub fn roll_over(&mut self, now: NaiveDateTime) -> bool {
+        let repeat = match &self.preferences.repeat {
+            Some(repeat) if self.preferences.expire <= now => repeat,
+            _ => return false,
+        };
+
+        while self.preferences.expire <= now {
+            self.preferences.expire = repeat.next_after(self.preferences.expire);
+        }
+
+        self.done = false;
+        self.exp_added = false;
+
+        for subtask in &mut self.subtasks {
+            subtask.done = false;
+            subtask.exp_added = false;
+        }
+
+        true
+    }
+
+    /// Done and total checklist items, `None` without a checklist.
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        if self.subtasks.is_empty() {
+            return None;
+        }
+
+        let done = self.subtasks.iter().filter(|s| s.done).count();
+
+        Some((done, self.subtasks.len()))
+    }
+}
+
+/// Reads a tag, with or without a leading `#`, as lowercase. Tags are made
+/// of letters, digits, `-`, `_` and `/`.
+pub fn parse_tag(word: &str) -> Result<String> {
+    let tag = word.strip_prefix('#').unwrap_or(word).to_lowercase();
+
+    if tag.is_empty() || !tag.chars().all(|c| c.is_alphanumeric() || "-_/".contains(c)) {
+        bail!("Invalid tag: {}", word)
+    }
+
+    Ok(tag)
+}
+
+/// Reads tags separated by spaces or commas.
+pub fn parse_tags(input: &str) -> Result<BTreeSet<String>> {
+    input
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|word| !word.is_empty())
+        .map(parse_tag)
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Preferences {
+    pub repeat: Option<Recurrence>,
+    pub expire: NaiveDateTime,
+    pub exp: u32,
+    /// Mark the task done once every checklist item is done.
+    pub auto_complete: bool,
+    // TODO
+    // Another parameters
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        let now = Local::now().naive_local();
+
+        let expire = match now.with_day(now.day() + 1) {
+            Some(date) => date,
+            None => match now.with_month(now.month() + 1) {
+                Some(date) => date.with_day(1).unwrap(),
+                None => now
+                    .with_year(now.year() + 1)
+                    .unwrap()
+                    .with_month(1)
+                    .unwrap()
+                    .with_day(1)
+                    .unwrap(),
+            },
+        };
+
+        Self {
+            repeat: None,
+            expire,
+            exp: 25,
+            auto_complete: false,
+        }
+    }
+}
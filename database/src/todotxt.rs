@@ -0,0 +1,503 @@
+//! [todo.txt](https://github.com/todotxt/todo.txt) lines for tasks.
+//!
+//! A task becomes `x <completed> <created> <title> @<tag> due:<expire> tors:<id>`,
+//! tags being todo.txt contexts. Only the date of `expire` is kept and the
+//! description is not written; both are left alone when a line updates an
+//! existing task.
+//!
+//! Priorities are `(A)` for urgent down to `(D)` for low at the start of
+//! the line, later letters reading as low. Done tasks keep theirs in a
+//! `pri:` key, as todo.txt drops the leading priority on completion.
+
+use crate::{
+    parse_tag, Database, ImportMode, ImportReport, Priority, SyncState, Task, TaskId, TaskState,
+};
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Key holding the task id, so lines can be matched to tasks.
+const ID_KEY: &str = "tors";
+const DUE_KEY: &str = "due";
+const PRIORITY_KEY: &str = "pri";
+
+/// Fields read from a todo.txt line.
+pub struct Line {
+    pub id: Option<TaskId>,
+    pub title: String,
+    pub done: bool,
+    pub completed: Option<NaiveDate>,
+    pub created: Option<NaiveDate>,
+    pub due: Option<NaiveDate>,
+    pub contexts: BTreeSet<String>,
+    pub priority: Priority,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub to_database: usize,
+    pub to_file: usize,
+    /// Tasks changed on both sides since the last sync, left untouched.
+    pub conflicts: Vec<TaskId>,
+}
+
+impl Line {
+    pub fn parse(line: &str) -> Result<Self> {
+        let mut words = line.split_whitespace().peekable();
+
+        let done = words.next_if_eq(&"x").is_some();
+        let priority = match done {
+            false => words.next_if(|w| is_priority(w)).map_or(Priority::None, |w| {
+                parse_priority(w.as_bytes()[1])
+            }),
+            true => Priority::None,
+        };
+        let mut completed = None;
+        let mut created = words.next_if(|w| parse_date(w).is_ok()).map(parse_date).transpose()?;
+
+        if done {
+            if let Some(date) = words.next_if(|w| parse_date(w).is_ok()) {
+                completed = created;
+                created = Some(parse_date(date)?);
+            } else {
+                completed = created.take();
+            }
+        }
+
+        let mut line = Self {
+            id: None,
+            title: String::new(),
+            done,
+            completed,
+            created,
+            due: None,
+            contexts: BTreeSet::new(),
+            priority,
+        };
+        let mut title = Vec::new();
+
+        for word in words {
+            if let Some(Ok(tag)) = word.strip_prefix('@').map(parse_tag) {
+                line.contexts.insert(tag);
+                continue;
+            }
+
+            match word.split_once(':') {
+                Some((ID_KEY, id)) if !id.is_empty() => line.id = Some(id.to_string()),
+                Some((DUE_KEY, date)) if parse_date(date).is_ok() => {
+                    line.due = Some(parse_date(date)?)
+                }
+                Some((PRIORITY_KEY, letter)) if matches!(letter.as_bytes(), [b'A'..=b'Z']) => {
+                    line.priority = parse_priority(letter.as_bytes()[0])
+                }
+                _ => title.push(word),
+            }
+        }
+
+        line.title = title.join(" ");
+
+        Ok(line)
+    }
+
+    pub fn from_task(id: &str, task: &Task) -> Self {
+        Self {
+            id: Some(id.to_string()),
+            title: task.title.clone(),
+            done: task.done,
+            completed: task
+                .done
+                .then(|| task.completions.last().unwrap_or(&task.creation_date).date()),
+            created: Some(task.creation_date.date()),
+            due: Some(task.preferences.expire.date()),
+            contexts: task.tags.clone(),
+            priority: task.priority,
+        }
+    }
+
+    /// Applies the line to `task`, keeping what todo.txt can't express.
+    pub fn update(&self, task: &mut Task) {
+        task.title = self.title.clone();
+        task.tags = self.contexts.clone();
+        task.priority = self.priority;
+
+        if self.done != task.done {
+            let completed = self.completed.or(self.created).unwrap_or_default();
+
+            task.set_done(self.done, completed.and_time(NaiveTime::MIN));
+        }
+
+        if let Some(created) = self.created {
+            if created != task.creation_date.date() {
+                task.creation_date = created.and_time(NaiveTime::MIN);
+            }
+        }
+
+        if let Some(due) = self.due {
+            if due != task.preferences.expire.date() {
+                task.preferences.expire = due.and_hms_opt(23, 59, 59).unwrap();
+            }
+        }
+    }
+
+    pub fn to_task(&self, now: NaiveDateTime) -> Task {
+        let mut task = Task {
+            creation_date: now,
+            ..Default::default()
+        };
+
+        self.update(&mut task);
+
+        task
+    }
+
+    fn hash(&self) -> String {
+        hex::encode(Sha256::digest(self.to_string().as_bytes()))
+    }
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.done {
+            write!(f, "x ")?;
+
+            if let Some(completed) = self.completed {
+                write!(f, "{} ", completed.format(DATE_FORMAT))?;
+            }
+        } else if let Some(letter) = priority_letter(self.priority) {
+            write!(f, "({}) ", letter)?;
+        }
+
+        if let Some(created) = self.created {
+            write!(f, "{} ", created.format(DATE_FORMAT))?;
+        }
+
+        write!(f, "{}", self.title)?;
+
+        for context in &self.contexts {
+            write!(f, " @{}", context)?;
+        }
+
+        if let Some(due) = self.due {
+            write!(f, " {}:{}", DUE_KEY, due.format(DATE_FORMAT))?;
+        }
+
+        match priority_letter(self.priority) {
+            Some(letter) if self.done => write!(f, " {}:{}", PRIORITY_KEY, letter)?,
+            _ => {}
+        }
+
+        if let Some(id) = &self.id {
+            write!(f, " {}:{}", ID_KEY, id)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Records `task` as synced and returns its line.
+fn synced(state: &SyncState, id: &str, task: &Task) -> Result<String> {
+    let line = Line::from_task(id, task);
+
+    state.set(id, &line.hash())?;
+
+    Ok(line.to_string())
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(date, DATE_FORMAT).with_context(|| format!("Invalid date: {}", date))
+}
+
+fn is_priority(word: &str) -> bool {
+    matches!(word.as_bytes(), [b'(', b'A'..=b'Z', b')'])
+}
+
+fn parse_priority(letter: u8) -> Priority {
+    match letter {
+        b'A' => Priority::Urgent,
+        b'B' => Priority::High,
+        b'C' => Priority::Medium,
+        _ => Priority::Low,
+    }
+}
+
+fn priority_letter(priority: Priority) -> Option<char> {
+    match priority {
+        Priority::None => None,
+        Priority::Low => Some('D'),
+        Priority::Medium => Some('C'),
+        Priority::High => Some('B'),
+        Priority::Urgent => Some('A'),
+    }
+}
+
+impl Database {
+    /// Writes every task that isn't archived.
+    pub fn export_todotxt<W: Write>(&self, mut writer: W, now: NaiveDateTime) -> Result<()> {
+        for (id, task) in self.list_tasks()? {
+            if task.state(now) != TaskState::Archived {
+                writeln!(writer, "{}", Line::from_task(&id, &task))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lines without a `tors:` id become new tasks.
+    pub fn import_todotxt<R: BufRead>(
+        &self,
+        reader: R,
+        mode: ImportMode,
+        now: NaiveDateTime,
+    ) -> Result<ImportReport> {
+        let mut tasks = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let line = Line::parse(&line)?;
+            let id = line.id.clone().unwrap_or_else(|| nanoid::nanoid!());
+
+            tasks.push((id, line.to_task(now)));
+        }
+
+        self.import(tasks, None, mode)
+    }
+
+    /// Two-way sync with a todo.txt file.
+    ///
+    /// Hashes of every line as of the last sync tell which side changed a
+    /// task; a task changed on both sides is reported as a conflict and
+    /// left as it is on each side. Archived tasks are removed from the file.
+    pub fn sync_todotxt(&self, path: &Path, now: NaiveDateTime) -> Result<SyncReport> {
+        let path = match path.canonicalize() {
+            Ok(path) => path,
+            Err(_) => {
+                fs::write(path, "")?;
+                path.canonicalize()?
+            }
+        };
+        let state = self.sync_state(&format!("todotxt:{}", path.display()))?;
+
+        let content = fs::read_to_string(&path)?;
+
+        self.batch(|| {
+            let mut tasks = self
+                .list_tasks()?
+                .into_iter()
+                .filter(|(_, task)| task.state(now) != TaskState::Archived)
+                .collect::<HashMap<_, _>>();
+            let mut seen = HashSet::new();
+            let mut output = Vec::new();
+            let mut report = SyncReport::default();
+
+            for text in content.lines() {
+                if text.trim().is_empty() {
+                    continue;
+                }
+
+                let line = Line::parse(text)?;
+
+                let id = match &line.id {
+                    Some(id) if seen.insert(id.clone()) => id.clone(),
+                    Some(_) => {
+                        output.push(text.to_string());
+                        continue;
+                    }
+                    None => {
+                        let task = line.to_task(now);
+                        let id = self.create_task(&task)?;
+
+                        output.push(synced(&state, &id, &task)?);
+                        report.to_database += 1;
+                        continue;
+                    }
+                };
+
+                let base = state.get(&id)?;
+                let file_hash = line.hash();
+
+                match tasks.remove(&id) {
+                    Some(mut task) => {
+                        let database_hash = Line::from_task(&id, &task).hash();
+
+                        if file_hash == database_hash {
+                            output.push(text.to_string());
+                            state.set(&id, &file_hash)?;
+                        } else if base.as_ref() == Some(&file_hash) {
+                            output.push(synced(&state, &id, &task)?);
+                            report.to_file += 1;
+                        } else if base.as_ref() == Some(&database_hash) {
+                            line.update(&mut task);
+                            self.update_task(&id, &task)?;
+                            output.push(synced(&state, &id, &task)?);
+                            report.to_database += 1;
+                        } else {
+                            output.push(text.to_string());
+                            report.conflicts.push(id);
+                        }
+                    }
+                    None if base.is_none() => {
+                        let task = line.to_task(now);
+
+                        self.insert_task(&id, &task)?;
+                        output.push(synced(&state, &id, &task)?);
+                        report.to_database += 1;
+                    }
+                    None if base == Some(file_hash) => {
+                        state.remove(&id)?;
+                        report.to_file += 1;
+                    }
+                    None => {
+                        output.push(text.to_string());
+                        report.conflicts.push(id);
+                    }
+                }
+            }
+
+            let mut remaining = tasks.into_iter().collect::<Vec<_>>();
+            remaining.sort_unstable_by_key(|(_, task)| task.creation_date);
+
+            for (id, task) in remaining {
+                let line = Line::from_task(&id, &task);
+
+                match state.get(&id)? {
+                    Some(base) if base == line.hash() => {
+                        self.delete_task(&id)?;
+                        state.remove(&id)?;
+                        report.to_database += 1;
+                    }
+                    Some(_) => report.conflicts.push(id),
+                    None => {
+                        output.push(synced(&state, &id, &task)?);
+                        report.to_file += 1;
+                    }
+                }
+            }
+
+            let mut content = output.join("\n");
+            content.push('\n');
+            fs::write(&path, content)?;
+
+            Ok(report)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Preferences;
+
+    fn date(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2022, 9, d).unwrap()
+    }
+
+    #[test]
+    fn parses_lines() {
+        let line = Line::parse("x 2022-09-12 2022-09-10 Water +home @balcony due:2022-09-11 tors:abc")
+            .unwrap();
+
+        assert!(line.done);
+        assert_eq!(line.completed, Some(date(12)));
+        assert_eq!(line.created, Some(date(10)));
+        assert_eq!(line.due, Some(date(11)));
+        assert_eq!(line.id.as_deref(), Some("abc"));
+        assert_eq!(line.title, "Water +home");
+        assert!(line.contexts.contains("balcony"));
+        assert_eq!(
+            line.to_string(),
+            "x 2022-09-12 2022-09-10 Water +home @balcony due:2022-09-11 tors:abc"
+        );
+
+        let line = Line::parse("(A) 2022-09-10 Call mom").unwrap();
+
+        assert!(!line.done);
+        assert_eq!(line.created, Some(date(10)));
+        assert_eq!(line.title, "Call mom");
+        assert_eq!(line.priority, Priority::Urgent);
+        assert_eq!(line.to_string(), "(A) 2022-09-10 Call mom");
+
+        let line = Line::parse("x 2022-09-12 Call mom pri:B").unwrap();
+
+        assert_eq!(line.priority, Priority::High);
+        assert_eq!(line.title, "Call mom");
+        assert_eq!(line.to_string(), "x 2022-09-12 Call mom pri:B");
+        assert_eq!(Line::parse("(F) Call mom").unwrap().priority, Priority::Low);
+    }
+
+    #[test]
+    fn task_round_trip() {
+        let text = "x 2022-09-12 2022-09-10 Water the plants due:2022-09-11 tors:abc";
+        let task = Line::parse(text).unwrap().to_task(date(1).and_time(NaiveTime::MIN));
+
+        assert!(task.done);
+        assert_eq!(task.completions, vec![date(12).and_time(NaiveTime::MIN)]);
+        assert_eq!(Line::from_task("abc", &task).to_string(), text);
+    }
+
+    #[test]
+    fn two_way_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todo.txt");
+        let now = date(10).and_hms_opt(12, 0, 0).unwrap();
+
+        let database = Database::temporary().unwrap();
+        let id = database
+            .create_task(&Task {
+                title: "From tors".to_string(),
+                creation_date: now,
+                preferences: Preferences {
+                    expire: date(11).and_hms_opt(18, 0, 0).unwrap(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .unwrap();
+        fs::write(&path, "From file\n").unwrap();
+
+        let report = database.sync_todotxt(&path, now).unwrap();
+        assert_eq!((report.to_database, report.to_file), (1, 1));
+        assert_eq!(database.list_tasks().unwrap().len(), 2);
+
+        let report = database.sync_todotxt(&path, now).unwrap();
+        assert_eq!((report.to_database, report.to_file), (0, 0));
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains(&format!("From tors due:2022-09-11 tors:{}", id)));
+
+        // Edited in the file only
+        fs::write(&path, content.replace("From tors", "Edited")).unwrap();
+        let report = database.sync_todotxt(&path, now).unwrap();
+        assert_eq!(report.to_database, 1);
+        assert_eq!(database.get_task(&id).unwrap().unwrap().title, "Edited");
+
+        // Edited on both sides
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, content.replace("Edited", "File")).unwrap();
+        let mut task = database.get_task(&id).unwrap().unwrap();
+        task.title = "Database".to_string();
+        database.update_task(&id, &task).unwrap();
+
+        let report = database.sync_todotxt(&path, now).unwrap();
+        assert_eq!(report.conflicts, vec![id.clone()]);
+
+        // Deleted in the database
+        let content = content.replace("Edited", "Database");
+        fs::write(&path, &content).unwrap();
+        database.sync_todotxt(&path, now).unwrap();
+        database.delete_task(&id).unwrap();
+
+        database.sync_todotxt(&path, now).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains(&id));
+    }
+}
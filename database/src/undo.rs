@@ -0,0 +1,279 @@
+//! Undo and redo of task and account changes.
+//!
+//! Every public method that changes tasks or the account, including the
+//! projects [`Database::delete_project`] and [`Database::import_json`] touch,
+//! adds one entry to the undo log with the values before and after; [`Database::batch`] groups several calls into
+//! one entry. The log keeps the last [`UNDO_LIMIT`]
+//! entries across sessions. Entries are JSON so that ones written before a
+//! schema change still decode, and they are encrypted like tasks.
+
+use crate::{Account, Database, Event, Project, ProjectId, Task, TaskId};
+use anyhow::{Context, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+
+pub(crate) const UNDO_TREE: &str = "undo";
+pub(crate) const REDO_TREE: &str = "redo";
+
+/// How many entries the undo log keeps.
+pub const UNDO_LIMIT: usize = 100;
+
+#[derive(Serialize, Deserialize)]
+struct Change {
+    id: TaskId,
+    before: Option<Task>,
+    after: Option<Task>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProjectChange {
+    id: ProjectId,
+    before: Option<Project>,
+    after: Option<Project>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    changes: Vec<Change>,
+    /// The account before and after.
+    account: Option<(Account, Account)>,
+    #[serde(default)]
+    projects: Vec<ProjectChange>,
+}
+
+impl Entry {
+    fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.account.is_none() && self.projects.is_empty()
+    }
+
+    /// The entry that undoes this one.
+    fn reversed(self) -> Self {
+        Self {
+            changes: self
+                .changes
+                .into_iter()
+                .rev()
+                .map(|c| Change {
+                    id: c.id,
+                    before: c.after,
+                    after: c.before,
+                })
+                .collect(),
+            account: self.account.map(|(before, after)| (after, before)),
+            projects: self
+                .projects
+                .into_iter()
+                .rev()
+                .map(|c| ProjectChange {
+                    id: c.id,
+                    before: c.after,
+                    after: c.before,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Database {
+    /// Runs `f`, recording every change it makes as a single undo step.
+    /// Nested calls join the outer step.
+    pub fn batch<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        if self.recording.borrow().is_some() {
+            return f();
+        }
+
+        *self.recording.borrow_mut() = Some(Entry::default());
+        let result = f();
+        let entry = self.recording.borrow_mut().take().unwrap_or_default();
+
+        if !entry.is_empty() {
+            self.push_entry(&self.undo, &entry)?;
+            self.redo.clear()?;
+        }
+
+        result
+    }
+
+    /// Reverts the last undo step, returning false if there was none.
+    pub fn undo(&self) -> Result<bool> {
+        self.step(&self.undo, &self.redo)
+    }
+
+    /// Applies the last undone step again, returning false if there was none.
+    pub fn redo(&self) -> Result<bool> {
+        self.step(&self.redo, &self.undo)
+    }
+
+    pub(crate) fn track_task(&self, id: &str, before: Option<Task>, after: Option<&Task>) {
+        if let Some(entry) = self.recording.borrow_mut().as_mut() {
+            match entry.changes.iter_mut().find(|c| c.id == id) {
+                Some(change) => change.after = after.cloned(),
+                None => entry.changes.push(Change {
+                    id: id.to_string(),
+                    before,
+                    after: after.cloned(),
+                }),
+            }
+        }
+    }
+
+    pub(crate) fn track_project(&self, id: &str, before: Option<Project>, after: Option<&Project>) {
+        if let Some(entry) = self.recording.borrow_mut().as_mut() {
+            match entry.projects.iter_mut().find(|c| c.id == id) {
+                Some(change) => change.after = after.cloned(),
+                None => entry.projects.push(ProjectChange {
+                    id: id.to_string(),
+                    before,
+                    after: after.cloned(),
+                }),
+            }
+        }
+    }
+
+    pub(crate) fn track_account(&self, before: Account, after: &Account) {
+        if let Some(entry) = self.recording.borrow_mut().as_mut() {
+            let before = entry.account.take().map_or(before, |(before, _)| before);
+
+            entry.account = Some((before, after.clone()));
+        }
+    }
+
+    /// Pops an entry from `from`, applies its `before` side and pushes it on `to`.
+    fn step(&self, from: &Tree, to: &Tree) -> Result<bool> {
+        self.unlocked()?;
+
+        let (key, data) = match from.last()? {
+            Some(record) => record,
+            None => return Ok(false),
+        };
+        let entry: Entry = serde_json::from_slice(&self.codec.unseal(&data)?)
+            .with_context(|| "Failed decode undo entry")?;
+
+        let now = Local::now().naive_local();
+
+        // Projects first, so that history names the ones tasks move back to
+        for change in entry.projects.iter().rev() {
+            match &change.before {
+                Some(project) => self.projects.insert(&change.id, self.encode(project)?)?,
+                None => self.projects.remove(&change.id)?,
+            };
+        }
+
+        for change in entry.changes.iter().rev() {
+            match &change.before {
+                Some(task) => self.store.insert(&change.id, task)?,
+                None => self.store.remove(&change.id)?,
+            }
+
+            self.record(
+                &change.id,
+                now,
+                Event::diff(change.after.as_ref(), change.before.as_ref()),
+            )?;
+        }
+
+        if let Some((before, _)) = &entry.account {
+            self.account.insert(crate::ACCOUNT_KEY, self.encode(before)?)?;
+        }
+
+        from.remove(key)?;
+        self.push_entry(to, &entry.reversed())?;
+
+        Ok(true)
+    }
+
+    fn push_entry(&self, tree: &Tree, entry: &Entry) -> Result<()> {
+        let data = self.codec.seal(serde_json::to_vec(entry)?)?;
+        let key = match tree.last()? {
+            Some((key, _)) => u64::from_be_bytes(key.as_ref().try_into()?) + 1,
+            None => 0,
+        };
+
+        tree.insert(key.to_be_bytes(), data)?;
+
+        while tree.len() > UNDO_LIMIT {
+            tree.pop_min()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn task(title: &str) -> Task {
+        Task {
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let database = Database::temporary().unwrap();
+
+        let id = database.create_task(&task("Water")).unwrap();
+        database.update_task(&id, &task("Watered")).unwrap();
+        database.delete_task(&id).unwrap();
+
+        assert!(database.undo().unwrap());
+        assert_eq!(database.get_task(&id).unwrap().unwrap().title, "Watered");
+
+        assert!(database.undo().unwrap());
+        assert_eq!(database.get_task(&id).unwrap().unwrap().title, "Water");
+
+        assert!(database.redo().unwrap());
+        assert_eq!(database.get_task(&id).unwrap().unwrap().title, "Watered");
+
+        database.update_task(&id, &task("Again")).unwrap();
+        assert!(!database.redo().unwrap());
+
+        assert!(database.undo().unwrap());
+        assert!(database.undo().unwrap());
+        assert!(database.undo().unwrap());
+        assert!(database.get_task(&id).unwrap().is_none());
+        assert!(!database.undo().unwrap());
+    }
+
+    #[test]
+    fn done_and_exp_are_one_step() {
+        let database = Database::temporary().unwrap();
+        let id = database.create_task(&task("Water")).unwrap();
+
+        database.set_done(&id, true, NaiveDateTime::default()).unwrap();
+        assert_eq!(database.account().unwrap().exp, 25);
+
+        database.undo().unwrap();
+        assert!(!database.get_task(&id).unwrap().unwrap().done);
+        assert_eq!(database.account().unwrap().exp, 0);
+
+        database.redo().unwrap();
+        assert!(database.get_task(&id).unwrap().unwrap().exp_added);
+        assert_eq!(database.account().unwrap().exp, 25);
+    }
+
+    #[test]
+    fn keeps_last_entries_across_sessions() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let database = Database::load(db.clone()).unwrap();
+
+        for i in 0..UNDO_LIMIT + 5 {
+            database.create_task(&task(&i.to_string())).unwrap();
+        }
+
+        drop(database);
+        let database = Database::load(db).unwrap();
+
+        let mut undone = 0;
+        while database.undo().unwrap() {
+            undone += 1;
+        }
+
+        assert_eq!(undone, UNDO_LIMIT);
+        assert_eq!(database.list_tasks().unwrap().len(), 5);
+    }
+}
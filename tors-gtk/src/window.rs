@@ -85,9 +85,9 @@ mod imp {
         }
 
         fn build_profile_stats(&self) -> Result<()> {
-            let db = &mut *DATABASE.lock().unwrap();
+            let db = &*DATABASE.lock().unwrap();
 
-            let account = &db.account;
+            let account = db.account()?;
 
             let level = gettext("Level: {0}").replace("{0}", &account.lvl.to_string());
             let experience = gettext("Experience: {0}").replace("{0}", &account.exp.to_string());
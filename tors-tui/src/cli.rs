@@ -0,0 +1,564 @@
+use crate::prompt;
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::path::PathBuf;
+use tors_caldav::Client;
+use tors_database::{
+    parse_deadline, parse_tags, Database, ImportMode, Preferences, Priority, ProjectId, Recurrence,
+    Sort, Subtask, Task, TaskQuery, TaskState,
+};
+
+/// Simple todo app with gamification. Starts the TUI without a command.
+#[derive(Parser)]
+#[command(name = "tors", version)]
+pub struct Cli {
+    /// Database directory, defaults to $TORS_DATA_DIR or ~/.local/share/tors
+    #[arg(long, global = true)]
+    pub data_dir: Option<PathBuf>,
+
+    /// Keep tasks as one Markdown file each in this directory
+    #[arg(long, global = true)]
+    pub tasks_dir: Option<PathBuf>,
+
+    /// Commit every change of the tasks directory to git
+    #[arg(long, global = true, requires = "tasks_dir")]
+    pub git: bool,
+
+    /// Keep tasks in this SQLite database
+    #[cfg(feature = "sqlite")]
+    #[arg(long, global = true, conflicts_with = "tasks_dir")]
+    pub sqlite: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Add a task and print its id
+    Add {
+        title: String,
+        #[arg(short, long, default_value_t)]
+        description: String,
+        /// Deadline like `2026-11-02 18:00`, `tomorrow 9am`, `next fri`, `in 3 days` or `eod`
+        #[arg(long)]
+        expire: Option<String>,
+        /// Experience awarded when done
+        #[arg(long)]
+        exp: Option<u32>,
+        /// `daily`, `weekly`, `monthly` or an RRULE
+        #[arg(long)]
+        repeat: Option<String>,
+        /// Checklist item, can be given several times
+        #[arg(long = "item")]
+        items: Vec<String>,
+        /// Mark the task done once every checklist item is done
+        #[arg(long)]
+        auto_complete: bool,
+        /// Name of the project the task belongs to
+        #[arg(long)]
+        project: Option<String>,
+        /// Tag of the task, can be given several times
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// `none`, `low`, `medium`, `high` or `urgent`
+        #[arg(long, default_value_t)]
+        priority: Priority,
+    },
+    /// List tasks
+    List {
+        #[arg(long)]
+        json: bool,
+        /// Include archived tasks
+        #[arg(long)]
+        all: bool,
+        /// Only tasks of this project
+        #[arg(long)]
+        project: Option<String>,
+        /// Only tasks with this tag, or without it when it starts with `-`,
+        /// can be given several times
+        #[arg(long = "tag", allow_hyphen_values = true)]
+        tags: Vec<String>,
+        /// `creation`, `due`, `priority`, `title` or `done-last`
+        #[arg(long, default_value_t)]
+        sort: Sort,
+        /// Filter like `done:no due:<2026-11-01 tag:work "text"`, other
+        /// words being searched fuzzily in titles and descriptions
+        #[arg(short, long, allow_hyphen_values = true)]
+        query: Option<String>,
+    },
+    /// Mark a task done
+    Done {
+        id: String,
+        /// Mark the task not done instead
+        #[arg(long)]
+        undo: bool,
+    },
+    /// Check a checklist item of a task
+    Check {
+        id: String,
+        /// Position of the item, starting at 1
+        item: usize,
+        /// Uncheck the item instead
+        #[arg(long)]
+        undo: bool,
+    },
+    /// Delete a task
+    Rm { id: String },
+    /// Show the history of a task
+    Log { id: String },
+    /// Revert the last change of tasks
+    Undo,
+    /// Apply the last undone change again
+    Redo,
+    /// Show level and experience
+    Stats {
+        /// Award more EXP for tasks of a higher priority
+        #[arg(long, value_enum)]
+        multipliers: Option<Switch>,
+    },
+    /// Manage projects
+    Project {
+        #[command(subcommand)]
+        command: ProjectCommand,
+    },
+    /// Manage tasks to be done before others
+    Dep {
+        #[command(subcommand)]
+        command: DepCommand,
+    },
+    /// Write all tasks (and the account, in JSON) to a file or stdout
+    Export {
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        /// Same as `--format todotxt`
+        #[arg(long)]
+        todotxt: bool,
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Read tasks from a file written by `export`
+    Import {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        /// Same as `--format todotxt`
+        #[arg(long)]
+        todotxt: bool,
+        /// Remove existing tasks first instead of merging
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Two-way sync with a todo.txt file or a CalDAV calendar
+    Sync {
+        #[arg(long)]
+        todotxt: Option<PathBuf>,
+        /// URL of a calendar collection
+        #[arg(long, conflicts_with = "todotxt")]
+        caldav: Option<String>,
+        /// CalDAV user, the password is read from $TORS_CALDAV_PASSWORD or prompted
+        #[arg(long, requires = "caldav")]
+        user: Option<String>,
+    },
+    /// Encrypt the database with a passphrase
+    Encrypt,
+    /// Remove encryption from the database
+    Decrypt,
+    /// Change the passphrase of an encrypted database
+    Passphrase,
+}
+
+#[derive(Subcommand)]
+pub enum ProjectCommand {
+    /// Add a project
+    Add { name: String },
+    /// List projects with their stats
+    List,
+    /// Rename a project
+    Rename { name: String, new_name: String },
+    /// Delete a project, its tasks are kept without a project
+    Rm { name: String },
+}
+
+#[derive(Subcommand)]
+pub enum DepCommand {
+    /// Make a task wait for another one
+    Add { id: String, on: String },
+    /// Stop a task waiting for another one
+    Rm { id: String, on: String },
+    /// Show the tasks a task waits for and the ones it blocks
+    Show { id: String },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Switch {
+    On,
+    Off,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    Json,
+    Csv,
+    Todotxt,
+    Ical,
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    id: &'a str,
+    #[serde(flatten)]
+    task: &'a Task,
+}
+
+impl Command {
+    pub fn run(self, mut database: Database) -> Result<()> {
+        let now = Local::now().naive_local();
+
+        match self {
+            Command::Add {
+                title,
+                description,
+                expire,
+                exp,
+                repeat,
+                items,
+                auto_complete,
+                project,
+                tags,
+                priority,
+            } => {
+                let mut preferences = Preferences {
+                    auto_complete,
+                    ..Default::default()
+                };
+
+                if let Some(expire) = expire {
+                    preferences.expire = parse_deadline(&expire, now)?;
+                }
+
+                if let Some(exp) = exp {
+                    preferences.exp = exp;
+                }
+
+                if let Some(repeat) = repeat {
+                    preferences.repeat = Some(Recurrence::parse(&repeat, preferences.expire)?);
+                }
+
+                let project = match project {
+                    Some(name) => Some(find_project(&database, &name)?),
+                    None => None,
+                };
+
+                let id = database.create_task(&Task {
+                    title,
+                    description,
+                    creation_date: now,
+                    project,
+                    tags: parse_tags(&tags.join(" "))?,
+                    priority,
+                    preferences,
+                    subtasks: items
+                        .into_iter()
+                        .map(|title| Subtask {
+                            title,
+                            ..Default::default()
+                        })
+                        .collect(),
+                    ..Default::default()
+                })?;
+
+                println!("{}", id);
+            }
+            Command::List {
+                json,
+                all,
+                project,
+                tags,
+                sort,
+                query: filter,
+            } => {
+                database.refresh(now)?;
+
+                let mut query = TaskQuery {
+                    sort,
+                    ..Default::default()
+                };
+                query.filter_tags(&tags.join(" "))?;
+                query.filter(&filter.unwrap_or_default())?;
+
+                if let Some(name) = project {
+                    query.project = Some(find_project(&database, &name)?);
+                }
+
+                if !all {
+                    query.states = vec![TaskState::Active, TaskState::Overdue, TaskState::Completed];
+                }
+
+                let tasks = database.query_tasks(&query, now)?;
+                let blocked = database.blocked_tasks()?;
+
+                if json {
+                    let entries = tasks
+                        .iter()
+                        .map(|(id, task)| Entry { id, task })
+                        .collect::<Vec<_>>();
+
+                    println!("{}", serde_json::to_string_pretty(&entries)?);
+                } else {
+                    for (id, task) in &tasks {
+                        let progress = task
+                            .progress()
+                            .map_or(String::new(), |(done, total)| format!(" {}/{}", done, total));
+                        let tags = task
+                            .tags
+                            .iter()
+                            .map(|tag| format!(" #{}", tag))
+                            .collect::<String>();
+                        let priority = match task.priority {
+                            Priority::None => String::new(),
+                            priority => format!("({}) ", priority),
+                        };
+
+                        println!(
+                            "{}  [{}] {}{}{}{}  (expire {}){}",
+                            id,
+                            if task.done { "x" } else { " " },
+                            priority,
+                            task.title,
+                            progress,
+                            tags,
+                            task.preferences.expire.format("%Y-%m-%d %H:%M:%S"),
+                            if blocked.contains(id) { "  blocked" } else { "" }
+                        );
+                    }
+                }
+            }
+            Command::Done { id, undo } => database.set_done(&id, !undo, now)?,
+            Command::Check { id, item, undo } => {
+                if item == 0 {
+                    bail!("Checklist items start at 1")
+                }
+
+                database.set_subtask_done(&id, item - 1, !undo, now)?
+            }
+            Command::Rm { id } => {
+                if database.get_task(&id)?.is_none() {
+                    bail!("Not found task {}", id)
+                }
+
+                database.delete_task(&id)?;
+            }
+            Command::Log { id } => {
+                let history = database.task_history(&id)?;
+
+                // Tasks created before history was recorded have none
+                if history.is_empty() && database.get_task(&id)?.is_none() {
+                    bail!("Not found task {}", id)
+                }
+
+                for entry in history {
+                    println!("{}  {}", entry.time.format("%Y-%m-%d %H:%M:%S"), entry.event);
+                }
+            }
+            Command::Undo => {
+                if !database.undo()? {
+                    println!("Nothing to undo");
+                }
+            }
+            Command::Redo => {
+                if !database.redo()? {
+                    println!("Nothing to redo");
+                }
+            }
+            Command::Stats { multipliers } => {
+                if let Some(multipliers) = multipliers {
+                    database.set_exp_multipliers(matches!(multipliers, Switch::On))?;
+                }
+
+                let account = database.account()?;
+
+                println!("Level: {}", account.lvl);
+                println!("Exp: {}", account.exp);
+                println!("Exp to next level: {}", account.next_level_exp());
+                println!("Streak: {} days", database.streak(now)?);
+                println!(
+                    "Priority multipliers: {}",
+                    if database.exp_multipliers()? { "on" } else { "off" }
+                );
+            }
+            Command::Project { command } => match command {
+                ProjectCommand::Add { name } => {
+                    println!("{}", database.create_project(&name, now)?);
+                }
+                ProjectCommand::List => {
+                    for (id, project) in database.projects()? {
+                        let stats = database.project_stats(&id, now)?;
+
+                        println!(
+                            "{}  {}  ({}/{} done, {} overdue, {} archived, {} exp)",
+                            id,
+                            project.name,
+                            stats.done,
+                            stats.tasks,
+                            stats.overdue,
+                            stats.archived,
+                            stats.exp
+                        );
+                    }
+                }
+                ProjectCommand::Rename { name, new_name } => {
+                    database.rename_project(&find_project(&database, &name)?, &new_name)?
+                }
+                ProjectCommand::Rm { name } => {
+                    database.delete_project(&find_project(&database, &name)?)?
+                }
+            },
+            Command::Dep { command } => match command {
+                DepCommand::Add { id, on } => database.add_dependency(&id, &on)?,
+                DepCommand::Rm { id, on } => database.remove_dependency(&id, &on)?,
+                DepCommand::Show { id } => {
+                    if database.get_task(&id)?.is_none() {
+                        bail!("Not found task {}", id)
+                    }
+
+                    for (title, chain) in [
+                        ("Waits for:", database.upstream(&id)?),
+                        ("Blocks:", database.downstream(&id)?),
+                    ] {
+                        println!("{}", title);
+
+                        for link in chain {
+                            println!(
+                                "{}{}  [{}] {}",
+                                "  ".repeat(link.depth),
+                                link.id,
+                                if link.task.done { "x" } else { " " },
+                                link.task.title
+                            );
+                        }
+                    }
+                }
+            },
+            Command::Export {
+                format,
+                todotxt,
+                output,
+            } => {
+                let writer: Box<dyn io::Write> = match output {
+                    Some(path) => Box::new(File::create(path)?),
+                    None => Box::new(io::stdout()),
+                };
+
+                match if todotxt { Format::Todotxt } else { format } {
+                    Format::Json => database.export_json(writer)?,
+                    Format::Csv => database.export_csv(writer)?,
+                    Format::Todotxt => database.export_todotxt(writer, now)?,
+                    Format::Ical => database.export_ical(writer, now)?,
+                }
+            }
+            Command::Import {
+                file,
+                format,
+                todotxt,
+                replace,
+            } => {
+                let reader = File::open(&file)
+                    .with_context(|| format!("Failed open {}", file.display()))?;
+                let mode = if replace {
+                    ImportMode::Replace
+                } else {
+                    ImportMode::Merge
+                };
+
+                let report = match if todotxt { Format::Todotxt } else { format } {
+                    Format::Json => database.import_json(reader, mode)?,
+                    Format::Csv => database.import_csv(reader, mode)?,
+                    Format::Todotxt => database.import_todotxt(BufReader::new(reader), mode, now)?,
+                    Format::Ical => database.import_ical(reader, mode, now)?,
+                };
+
+                println!("Imported {} tasks", report.imported);
+
+                if report.updated > 0 {
+                    println!("Updated {} tasks", report.updated);
+                }
+
+                for id in report.duplicates {
+                    println!("Skipped existing task {}", id);
+                }
+
+                for warning in report.warnings {
+                    println!("Warning: {}", warning);
+                }
+            }
+            Command::Sync {
+                todotxt,
+                caldav,
+                user,
+            } => {
+                let (to_database, to_target, target, conflicts, skipped) = match (todotxt, caldav) {
+                    (Some(path), _) => {
+                        let report = database.sync_todotxt(&path, now)?;
+
+                        (
+                            report.to_database,
+                            report.to_file,
+                            path.display().to_string(),
+                            report.conflicts,
+                            Vec::new(),
+                        )
+                    }
+                    (None, Some(url)) => {
+                        let mut client = Client::new(&url)?;
+
+                        if let Some(user) = user {
+                            client = client.with_credentials(&user, &prompt::caldav_password()?);
+                        }
+
+                        let report = tors_caldav::sync(&database, &client, now)?;
+
+                        (
+                            report.to_database,
+                            report.to_server,
+                            url,
+                            report.conflicts,
+                            report.skipped,
+                        )
+                    }
+                    (None, None) => bail!("Nothing to sync, pass --todotxt or --caldav"),
+                };
+
+                println!(
+                    "Updated {} tasks in tors and {} in {}",
+                    to_database, to_target, target
+                );
+
+                for id in conflicts {
+                    println!("Conflict: task {} changed on both sides", id);
+                }
+
+                for (href, reason) in skipped {
+                    println!("Skipped {}: {}", href, reason);
+                }
+            }
+            Command::Encrypt => database.enable_encryption(&prompt::new_passphrase()?)?,
+            Command::Decrypt => database.disable_encryption()?,
+            Command::Passphrase => database.change_passphrase(&prompt::new_passphrase()?)?,
+        }
+
+        Ok(())
+    }
+}
+
+fn find_project(database: &Database, name: &str) -> Result<ProjectId> {
+    match database.find_project(name)? {
+        Some((id, _)) => Ok(id),
+        None => bail!("Not found project {}", name),
+    }
+}
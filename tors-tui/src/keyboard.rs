@@ -1,12 +1,12 @@
-use crate::ui::{EditMode, EditState, WindowMode};
+use crate::ui::{EditMode, EditState, TaskView, WindowMode};
 use crate::App;
 use anyhow::Result;
 use crossterm::event;
-use crossterm::event::{Event, KeyCode};
+use crossterm::event::{Event, KeyCode, KeyModifiers};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use unicode_width::UnicodeWidthStr;
-use crate::task::Task;
+use tors_database::{parse_deadline, parse_tags, Blocked, Priority, Recurrence, Subtask, Task, TaskQuery};
 
 #[derive(Debug)]
 pub struct ExitApp;
@@ -22,12 +22,40 @@ impl Error for ExitApp {}
 impl App {
     pub fn event(&mut self) -> Result<()> {
         if let Event::Key(key) = event::read()? {
+            self.message = None;
+
             match self.mode {
                 WindowMode::List => match key.code {
                     KeyCode::Char(' ') => self.mark_task()?,
                     KeyCode::Char('n') => self.new_task()?,
                     KeyCode::Char('d') => self.delete_task()?,
                     KeyCode::Char('s') => self.mode = WindowMode::Stats,
+                    KeyCode::Char('a') => self.archive_task()?,
+                    KeyCode::Char('u') => self.undo()?,
+                    KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        self.redo()?
+                    }
+                    KeyCode::Char('P') if self.view == TaskView::Archive => self.purge_archive()?,
+                    KeyCode::Tab => self.switch_view()?,
+                    KeyCode::Char(']') => self.switch_project(true)?,
+                    KeyCode::Char('[') => self.switch_project(false)?,
+                    KeyCode::Char('N') => self.mode = WindowMode::NewProject,
+                    KeyCode::Char('D') if self.task().is_some() => {
+                        self.update_dependencies()?;
+                        self.mode = WindowMode::Dependencies(false);
+                    }
+                    KeyCode::Char('f') => {
+                        self.filter_input = self.tag_filter.clone();
+                        self.mode = WindowMode::Filter;
+                    }
+                    KeyCode::Char('/') => {
+                        self.search_input = self.search.clone();
+                        self.mode = WindowMode::Search;
+                    }
+                    KeyCode::Char('o') => {
+                        self.sort = self.sort.next();
+                        self.update_tasks()?
+                    }
                     // KeyCode::Char('a') => self.config.add_exp(30)?,
                     KeyCode::Down => self.tasks.next(),
                     KeyCode::Up => self.tasks.previous(),
@@ -47,6 +75,7 @@ impl App {
                     KeyCode::Char('p') => {
                         self.mode = WindowMode::Preferences(false);
                     }
+                    KeyCode::Char('c') => self.edit_checklist(),
                     _ => {}
                 },
                 WindowMode::Task(EditMode::Edit(EditState::Title)) => {
@@ -69,6 +98,40 @@ impl App {
                     KeyCode::Backspace => self.description_bs(),
                     _ => {}
                 },
+                WindowMode::Task(EditMode::Edit(EditState::Checklist)) => match key.code {
+                    KeyCode::Esc => self.mode = WindowMode::Task(EditMode::View),
+                    KeyCode::Up => self.checklist.previous(),
+                    KeyCode::Down => self.checklist.next(),
+                    KeyCode::Char(' ') => self.mark_subtask()?,
+                    KeyCode::Char('n') => self.new_subtask(),
+                    KeyCode::Char('d') => self.delete_subtask(),
+                    KeyCode::Char('+') => self.subtask_exp(true),
+                    KeyCode::Char('-') => self.subtask_exp(false),
+                    KeyCode::Enter if self.checklist.state.selected().is_some() => {
+                        self.mode = WindowMode::Task(EditMode::Edit(EditState::Subtask))
+                    }
+                    _ => {}
+                },
+                WindowMode::Task(EditMode::Edit(EditState::Subtask)) => {
+                    let (_, task) = self.task().unwrap();
+                    let task = &mut *task.borrow_mut();
+
+                    let subtask = match self.checklist.state.selected() {
+                        Some(i) => &mut task.subtasks[i],
+                        None => return Ok(()),
+                    };
+
+                    match key.code {
+                        KeyCode::Esc | KeyCode::Enter => {
+                            self.mode = WindowMode::Task(EditMode::Edit(EditState::Checklist))
+                        }
+                        KeyCode::Char(c) => input(&mut subtask.title, self.width, c),
+                        KeyCode::Backspace => {
+                            subtask.title.pop();
+                        }
+                        _ => {}
+                    }
+                }
                 WindowMode::Preferences(false) => match key.code {
                     KeyCode::Char('e') => self.preferences_edit()?,
                     KeyCode::Esc => self.back_to_task(),
@@ -78,16 +141,87 @@ impl App {
                 },
                 WindowMode::Preferences(true) => match key.code {
                     KeyCode::Esc => self.back_to_pref(),
-                    KeyCode::Char(c) => input(&mut self.preferences_input, self.width, c),
+                    KeyCode::Char(c) => {
+                        self.preferences_error = None;
+                        input(&mut self.preferences_input, self.width, c)
+                    }
                     KeyCode::Backspace => {
+                        self.preferences_error = None;
                         self.preferences_input.pop();
                     }
                     KeyCode::Enter => self.preferences_edit()?,
                     _ => {}
                 },
-                WindowMode::Stats => if key.code == KeyCode::Esc {
-                    self.back_to_list();
-                }
+                WindowMode::NewProject => match key.code {
+                    KeyCode::Esc => {
+                        self.project_input.clear();
+                        self.back_to_list();
+                    }
+                    KeyCode::Char(c) => input(&mut self.project_input, self.width, c),
+                    KeyCode::Backspace => {
+                        self.project_input.pop();
+                    }
+                    KeyCode::Enter => self.new_project()?,
+                    _ => {}
+                },
+                WindowMode::Filter => match key.code {
+                    KeyCode::Esc => self.back_to_list(),
+                    KeyCode::Char(c) => input(&mut self.filter_input, self.width, c),
+                    KeyCode::Backspace => {
+                        self.filter_input.pop();
+                    }
+                    KeyCode::Enter => self.apply_filter()?,
+                    _ => {}
+                },
+                WindowMode::Search => match key.code {
+                    KeyCode::Esc => {
+                        self.search_input.clear();
+                        self.search_changed()?;
+                        self.back_to_list();
+                    }
+                    KeyCode::Enter => self.back_to_list(),
+                    KeyCode::Down => self.tasks.next(),
+                    KeyCode::Up => self.tasks.previous(),
+                    KeyCode::Char(c) => {
+                        input(&mut self.search_input, self.width, c);
+                        self.search_changed()?
+                    }
+                    KeyCode::Backspace => {
+                        self.search_input.pop();
+                        self.search_changed()?
+                    }
+                    _ => {}
+                },
+                WindowMode::Dependencies(false) => match key.code {
+                    KeyCode::Esc => self.back_to_list(),
+                    KeyCode::Up => self.upstream.previous(),
+                    KeyCode::Down => self.upstream.next(),
+                    KeyCode::Char('a') => self.mode = WindowMode::Dependencies(true),
+                    KeyCode::Char('d') => self.remove_dependency()?,
+                    _ => {}
+                },
+                WindowMode::Dependencies(true) => match key.code {
+                    KeyCode::Esc => {
+                        self.dependency_input.clear();
+                        self.dependency_error = None;
+                        self.mode = WindowMode::Dependencies(false);
+                    }
+                    KeyCode::Char(c) => input(&mut self.dependency_input, self.width, c),
+                    KeyCode::Backspace => {
+                        self.dependency_input.pop();
+                    }
+                    KeyCode::Enter => self.add_dependency()?,
+                    _ => {}
+                },
+                WindowMode::Stats => match key.code {
+                    KeyCode::Esc => self.back_to_list(),
+                    KeyCode::Char('m') => {
+                        let on = self.database.exp_multipliers()?;
+
+                        self.database.set_exp_multipliers(!on)?
+                    }
+                    _ => {}
+                },
             }
         }
         Ok(())
@@ -95,28 +229,92 @@ impl App {
 
     fn mark_task(&mut self) -> Result<()> {
         if let Some((id, task)) = self.task() {
-            let task = &mut *task.borrow_mut();
-            task.done = !task.done;
+            let done = !task.borrow().done;
 
-            if !task.exp_added {
-                task.exp_added = true;
-                self.database.add_exp(task.preferences.exp)?;
+            match self.database.set_done(id, done, chrono::Local::now().naive_local()) {
+                Err(e) if e.is::<Blocked>() => self.message = Some(e.to_string()),
+                result => result?,
             }
 
-            self.database.insert(id, task)?;
             self.update_tasks()?;
         }
 
         Ok(())
     }
 
+    /// Makes the selected task wait for the best match of the input, a
+    /// query like the search one.
+    fn add_dependency(&mut self) -> Result<()> {
+        let id = match self.task() {
+            Some((id, _)) => id.clone(),
+            None => return Ok(()),
+        };
+
+        let mut query = TaskQuery::default();
+
+        if let Err(e) = query.filter(&self.dependency_input) {
+            self.dependency_error = Some(e.to_string());
+
+            return Ok(());
+        }
+
+        let now = chrono::Local::now().naive_local();
+        let on = self
+            .database
+            .query_tasks(&query, now)?
+            .into_iter()
+            .map(|(on, _)| on)
+            .find(|on| *on != id);
+
+        let result = match on {
+            Some(on) => self.database.add_dependency(&id, &on),
+            None => Err(anyhow::anyhow!("No task matches")),
+        };
+
+        match result {
+            Ok(()) => {
+                self.dependency_input.clear();
+                self.dependency_error = None;
+                self.mode = WindowMode::Dependencies(false);
+                self.update_tasks()?;
+                self.update_dependencies()
+            }
+            Err(e) => {
+                self.dependency_error = Some(e.to_string());
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes the selected link of the chain, between the task and the
+    /// one it waits for directly.
+    fn remove_dependency(&mut self) -> Result<()> {
+        let (id, i) = match (self.task(), self.upstream.state.selected()) {
+            (Some((id, _)), Some(i)) => (id.clone(), i),
+            _ => return Ok(()),
+        };
+
+        let link = &self.upstream.items[i];
+        let parent = self.upstream.items[..i]
+            .iter()
+            .rev()
+            .find(|parent| parent.depth + 1 == link.depth)
+            .map_or(id, |parent| parent.id.clone());
+
+        self.database.remove_dependency(&parent, &link.id)?;
+        self.update_tasks()?;
+        self.update_dependencies()
+    }
+
     fn new_task(&mut self) -> Result<()> {
         let task = Task {
             title: "New task".to_string(),
             creation_date: chrono::Local::now().naive_local(),
+            project: self.project(),
             ..Default::default()
         };
-        self.database.add(&task)?;
+        self.database.create_task(&task)?;
         self.update_tasks()?;
 
         Ok(())
@@ -124,13 +322,114 @@ impl App {
 
     fn delete_task(&mut self) -> Result<()> {
         if let Some((id, _)) = self.task() {
-            self.database.remove(id)?;
+            self.database.delete_task(id)?;
             self.update_tasks()?;
         }
 
         Ok(())
     }
 
+    fn archive_task(&mut self) -> Result<()> {
+        if let Some((id, _)) = self.task() {
+            let now = chrono::Local::now().naive_local();
+
+            if self.view == TaskView::Archive {
+                self.database.restore_task(id, now)?;
+            } else {
+                self.database.archive_task(id, now)?;
+            }
+
+            self.update_tasks()?;
+        }
+
+        Ok(())
+    }
+
+    fn purge_archive(&mut self) -> Result<()> {
+        self.database.purge_archived()?;
+        self.update_tasks()
+    }
+
+    fn undo(&mut self) -> Result<()> {
+        self.database.undo()?;
+        self.update_tasks()
+    }
+
+    fn redo(&mut self) -> Result<()> {
+        self.database.redo()?;
+        self.update_tasks()
+    }
+
+    fn switch_project(&mut self, next: bool) -> Result<()> {
+        if next {
+            self.projects.next();
+        } else {
+            self.projects.previous();
+        }
+
+        self.tasks.state.select(None);
+        self.update_tasks()
+    }
+
+    /// Creates a project named by the input and lists its tasks. Names in
+    /// use are ignored like invalid preference values.
+    fn new_project(&mut self) -> Result<()> {
+        let name = self.project_input.trim();
+
+        if name.is_empty() || self.database.find_project(name)?.is_some() {
+            return Ok(());
+        }
+
+        let id = self
+            .database
+            .create_project(name, chrono::Local::now().naive_local())?;
+
+        self.project_input.clear();
+        self.update_tasks()?;
+
+        let i = self.projects.items.iter().position(|(p, _)| p.as_ref() == Some(&id));
+        self.projects.state.select(i);
+        self.tasks.state.select(None);
+        self.back_to_list();
+
+        self.update_tasks()
+    }
+
+    /// Lists tasks matching the filter input, ignoring an invalid one.
+    fn apply_filter(&mut self) -> Result<()> {
+        if TaskQuery::default().filter_tags(&self.filter_input).is_err() {
+            return Ok(());
+        }
+
+        self.tag_filter = self.filter_input.trim().to_string();
+        self.tasks.state.select(None);
+        self.back_to_list();
+
+        self.update_tasks()
+    }
+
+    /// Lists tasks matching the search input as it's typed, keeping the
+    /// last valid search while the input isn't a valid query.
+    fn search_changed(&mut self) -> Result<()> {
+        if let Err(e) = TaskQuery::default().filter(&self.search_input) {
+            self.search_error = Some(e.to_string());
+
+            return Ok(());
+        }
+
+        self.search_error = None;
+        self.search = self.search_input.trim().to_string();
+        self.tasks.state.select(Some(0));
+
+        self.update_tasks()
+    }
+
+    fn switch_view(&mut self) -> Result<()> {
+        self.view = self.view.next();
+        self.tasks.state.select(None);
+        self.update_tasks()
+    }
+
     fn edit_task(&mut self) -> Result<()> {
         if let Some((_, task)) = self.task() {
             let task = &mut *task.borrow_mut();
@@ -151,7 +450,7 @@ impl App {
         if task.title.is_empty() {
             return Ok(());
         } else {
-            self.database.insert(id, task)?;
+            self.database.update_task(id, task)?;
         }
 
         self.update_tasks()?;
@@ -160,6 +459,83 @@ impl App {
         Ok(())
     }
 
+    fn edit_checklist(&mut self) {
+        if self.checklist.state.selected().is_none() && !self.checklist.items.is_empty() {
+            self.checklist.state.select(Some(0));
+        }
+
+        self.mode = WindowMode::Task(EditMode::Edit(EditState::Checklist));
+    }
+
+    /// Saves the task as edited so far, then checks or unchecks the selected
+    /// item so its EXP is awarded.
+    fn mark_subtask(&mut self) -> Result<()> {
+        let i = match self.checklist.state.selected() {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let (id, task) = self.task().unwrap();
+        let task = &*task.borrow();
+
+        if task.title.is_empty() {
+            return Ok(());
+        }
+
+        let now = chrono::Local::now().naive_local();
+
+        self.database.batch(|| {
+            self.database.update_task(id, task)?;
+            self.database
+                .set_subtask_done(id, i, !task.subtasks[i].done, now)
+        })?;
+
+        self.update_tasks()
+    }
+
+    fn new_subtask(&mut self) {
+        let (_, task) = self.task().unwrap();
+        let task = &mut *task.borrow_mut();
+
+        task.subtasks.push(Subtask {
+            title: "New item".to_string(),
+            ..Default::default()
+        });
+
+        self.checklist.state.select(Some(task.subtasks.len() - 1));
+        self.mode = WindowMode::Task(EditMode::Edit(EditState::Subtask));
+    }
+
+    fn delete_subtask(&mut self) {
+        let (_, task) = self.task().unwrap();
+        let task = &mut *task.borrow_mut();
+
+        if let Some(i) = self.checklist.state.selected() {
+            task.subtasks.remove(i);
+            self.checklist.state.select(match task.subtasks.len() {
+                0 => None,
+                len => Some(i.min(len - 1)),
+            });
+        }
+    }
+
+    fn subtask_exp(&mut self, more: bool) {
+        let (_, task) = self.task().unwrap();
+        let task = &mut *task.borrow_mut();
+
+        if let Some(subtask) = self
+            .checklist
+            .state
+            .selected()
+            .and_then(|i| task.subtasks.get_mut(i))
+        {
+            subtask.exp = if more {
+                subtask.exp + 5
+            } else {
+                subtask.exp.saturating_sub(5)
+            };
+        }
+    }
+
     fn back_to_list(&mut self) {
         self.mode = WindowMode::List;
     }
@@ -172,6 +548,7 @@ impl App {
     fn back_to_pref(&mut self) {
         self.mode = WindowMode::Preferences(false);
         self.preferences_input.clear();
+        self.preferences_error = None;
     }
 
     fn description_input(&mut self, n: char) {
@@ -180,7 +557,7 @@ impl App {
 
         task.description.push(n);
 
-        if self.cursor_pos_x as u16 == self.width.saturating_sub(3) {
+        if self.cursor_pos_x == self.width.saturating_sub(3) {
             task.description.push('\n');
             self.cursor_pos_y += 1;
         }
@@ -212,25 +589,35 @@ impl App {
         let task = &mut *task.borrow_mut();
 
         if let Some(i) = self.preferences.state.selected() {
-            if i == 0 {
-                task.preferences.daily_repeat = !task.preferences.daily_repeat;
-
-                return Ok(());
-            } else if self.mode == WindowMode::Preferences(false) {
+            if self.mode == WindowMode::Preferences(false) {
                 self.mode = WindowMode::Preferences(true);
 
                 return Ok(());
             }
 
             match i {
-                1 => {
-                    if let Ok(date) = chrono::NaiveDateTime::parse_from_str(
-                        &self.preferences_input,
-                        "%Y-%m-%d %H:%M:%S",
-                    ) {
-                        task.preferences.expire = date;
+                0 => match self.preferences_input.trim() {
+                    "" | "none" => {
+                        task.preferences.repeat = None;
                         self.back_to_pref();
                     }
+                    input => {
+                        if let Ok(repeat) = Recurrence::parse(input, task.preferences.expire) {
+                            task.preferences.repeat = Some(repeat);
+                            self.back_to_pref();
+                        }
+                    }
+                },
+                1 => {
+                    let now = chrono::Local::now().naive_local();
+
+                    match parse_deadline(&self.preferences_input, now) {
+                        Ok(date) => {
+                            task.preferences.expire = date;
+                            self.back_to_pref();
+                        }
+                        Err(e) => self.preferences_error = Some(e.to_string()),
+                    }
                 }
                 2 => {
                     if let Ok(exp) = self.preferences_input.parse::<u32>() {
@@ -238,6 +625,41 @@ impl App {
                         self.back_to_pref();
                     }
                 }
+                3 => match self.preferences_input.trim() {
+                    "yes" => {
+                        task.preferences.auto_complete = true;
+                        self.back_to_pref();
+                    }
+                    "no" => {
+                        task.preferences.auto_complete = false;
+                        self.back_to_pref();
+                    }
+                    _ => {}
+                },
+                4 => {
+                    if let Ok(tags) = parse_tags(&self.preferences_input) {
+                        task.tags = tags;
+                        self.back_to_pref();
+                    }
+                }
+                5 => match self.preferences_input.trim() {
+                    "" | "none" => {
+                        task.project = None;
+                        self.back_to_pref();
+                    }
+                    name => {
+                        if let Some((id, _)) = self.database.find_project(name)? {
+                            task.project = Some(id);
+                            self.back_to_pref();
+                        }
+                    }
+                },
+                6 => {
+                    if let Ok(priority) = self.preferences_input.parse::<Priority>() {
+                        task.priority = priority;
+                        self.back_to_pref();
+                    }
+                }
                 _ => {}
             }
         }
@@ -1,25 +1,60 @@
+mod cli;
 mod keyboard;
+mod prompt;
 mod ui;
-mod task;
 
+use crate::cli::Cli;
+use crate::keyboard::ExitApp;
 use crate::ui::App;
 use anyhow::Result;
+use clap::Parser;
 use crossterm::execute;
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
 use std::io;
+use tors_database::{Database, FileStore};
 use tui::backend::CrosstermBackend;
 use tui::Terminal;
 
 fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let mut database = match cli.data_dir {
+        Some(path) => Database::open(path)?,
+        None => Database::new()?,
+    };
+
+    if let Some(dir) = cli.tasks_dir {
+        database = database.with_task_store(FileStore::open(dir, cli.git)?);
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = cli.sqlite {
+        database = database.with_task_store(tors_database::SqliteStore::open(path)?);
+    }
+
+    if database.is_locked() {
+        match prompt::unlock(&mut database) {
+            Err(err) if err.is::<ExitApp>() => return Ok(()),
+            res => res?,
+        }
+    }
+
+    match cli.command {
+        Some(command) => command.run(database),
+        None => run(database),
+    }
+}
+
+fn run(database: Database) -> Result<()> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let mut app = App::new()?;
+    let mut app = App::new(database);
     let res = app.run(&mut terminal);
 
     disable_raw_mode()?;
@@ -0,0 +1,88 @@
+use crate::keyboard::ExitApp;
+use anyhow::{bail, Result};
+use crossterm::event;
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use std::io::Write;
+use std::{env, io};
+use tors_database::{Database, WrongPassphrase};
+
+const UNLOCK_ATTEMPTS: usize = 3;
+
+/// Passphrase used instead of prompting, for scripts.
+const PASSPHRASE_ENV: &str = "TORS_PASSPHRASE";
+
+/// CalDAV password used instead of prompting, for scripts.
+const CALDAV_PASSWORD_ENV: &str = "TORS_CALDAV_PASSWORD";
+
+/// Reads a line from the terminal without echoing it.
+pub fn passphrase(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+
+    enable_raw_mode()?;
+    let passphrase = read_hidden();
+    disable_raw_mode()?;
+
+    eprintln!();
+
+    passphrase
+}
+
+fn read_hidden() -> Result<String> {
+    let mut input = String::new();
+
+    loop {
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Enter => return Ok(input),
+                KeyCode::Esc => return Err(ExitApp)?,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Err(ExitApp)?
+                }
+                KeyCode::Char(c) => input.push(c),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Asks for a new passphrase twice.
+pub fn new_passphrase() -> Result<String> {
+    let passphrase = passphrase("New passphrase: ")?;
+
+    if passphrase.is_empty() {
+        bail!("Passphrase can't be empty")
+    }
+
+    if passphrase != self::passphrase("Repeat passphrase: ")? {
+        bail!("Passphrases don't match")
+    }
+
+    Ok(passphrase)
+}
+
+pub fn unlock(database: &mut Database) -> Result<()> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        return database.unlock(&passphrase);
+    }
+
+    for _ in 1..UNLOCK_ATTEMPTS {
+        match database.unlock(&passphrase("Passphrase: ")?) {
+            Err(err) if err.is::<WrongPassphrase>() => eprintln!("{}", err),
+            res => return res,
+        }
+    }
+
+    database.unlock(&passphrase("Passphrase: ")?)
+}
+
+pub fn caldav_password() -> Result<String> {
+    match env::var(CALDAV_PASSWORD_ENV) {
+        Ok(password) => Ok(password),
+        Err(_) => passphrase("CalDAV password: "),
+    }
+}
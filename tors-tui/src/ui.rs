@@ -2,17 +2,20 @@ use anyhow::Result;
 use chrono::Local;
 use rayon::prelude::*;
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::env;
 use std::rc::Rc;
 use tui::backend::Backend;
-use tui::layout::{Alignment, Constraint, Layout};
+use tui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use tui::style::{Color, Modifier, Style};
 use tui::text::{Span, Spans};
 use tui::widgets::{Block, BorderType, Borders, List, ListItem, ListState, Paragraph};
 use tui::{Frame, Terminal};
 use unicode_width::UnicodeWidthStr;
-use crate::task::Task;
-use tors_database::Database;
+use tors_database::{
+    parse_deadline, ChainLink, Database, Priority, ProjectId, Sort, Task, TaskId, TaskQuery,
+    TaskState,
+};
 
 #[derive(Default, PartialEq, Eq)]
 pub enum WindowMode {
@@ -21,6 +24,45 @@ pub enum WindowMode {
     Task(EditMode),
     Preferences(bool),
     Stats,
+    NewProject,
+    Filter,
+    Search,
+    /// Whether a dependency is being added.
+    Dependencies(bool),
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum TaskView {
+    #[default]
+    Tasks,
+    Overdue,
+    Archive,
+}
+
+impl TaskView {
+    pub(crate) fn next(self) -> Self {
+        match self {
+            TaskView::Tasks => TaskView::Overdue,
+            TaskView::Overdue => TaskView::Archive,
+            TaskView::Archive => TaskView::Tasks,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            TaskView::Tasks => " Tasks ",
+            TaskView::Overdue => " Overdue ",
+            TaskView::Archive => " Archive ",
+        }
+    }
+
+    fn states(self) -> Vec<TaskState> {
+        match self {
+            TaskView::Tasks => vec![TaskState::Active, TaskState::Completed],
+            TaskView::Overdue => vec![TaskState::Overdue],
+            TaskView::Archive => vec![TaskState::Archived],
+        }
+    }
 }
 
 #[derive(PartialEq, Eq)]
@@ -33,33 +75,76 @@ pub enum EditMode {
 pub enum EditState {
     Title,
     Task,
+    Checklist,
+    Subtask,
 }
 
 pub struct App {
     pub(crate) database: Database,
     pub(crate) mode: WindowMode,
-    pub(crate) tasks: StatefulList<(String, Rc<RefCell<Task>>)>,
+    pub(crate) view: TaskView,
+    pub(crate) sort: Sort,
+    pub(crate) tasks: StatefulList<(TaskId, Rc<RefCell<Task>>)>,
+    /// Tasks waiting for one that isn't done.
+    pub(crate) blocked: HashSet<TaskId>,
+    /// Shown in the list title until the next key.
+    pub(crate) message: Option<String>,
+    /// Tasks the selected task waits for.
+    pub(crate) upstream: StatefulList<ChainLink>,
+    /// Tasks waiting for the selected task.
+    pub(crate) downstream: Vec<ChainLink>,
+    pub(crate) dependency_input: String,
+    pub(crate) dependency_error: Option<String>,
+    /// "All tasks" first, then every project.
+    pub(crate) projects: StatefulList<(Option<ProjectId>, String)>,
+    pub(crate) project_input: String,
+    /// Tag filter of the list, like `#work -#later`.
+    pub(crate) tag_filter: String,
+    pub(crate) filter_input: String,
+    /// Query of the incremental search, see [`TaskQuery::filter`].
+    pub(crate) search: String,
+    pub(crate) search_input: String,
+    /// Why the search input isn't a valid query.
+    pub(crate) search_error: Option<String>,
     pub(crate) preferences: StatefulList<String>,
     pub(crate) preferences_input: String,
+    /// Why the value confirmed with Enter was rejected, until the input changes.
+    pub(crate) preferences_error: Option<String>,
+    pub(crate) checklist: StatefulList<String>,
     pub(crate) cursor_pos_x: u16,
     pub(crate) cursor_pos_y: u16,
     pub(crate) width: u16,
 }
 
 impl App {
-    pub fn new() -> Result<Self> {
-        let database = Database::new()?;
-
-        Ok(Self {
+    pub fn new(database: Database) -> Self {
+        Self {
             database,
             mode: WindowMode::default(),
+            view: TaskView::default(),
+            sort: Sort::default(),
             tasks: StatefulList::default(),
+            blocked: HashSet::new(),
+            message: None,
+            upstream: StatefulList::default(),
+            downstream: Vec::new(),
+            dependency_input: String::new(),
+            dependency_error: None,
+            projects: StatefulList::default(),
+            project_input: String::new(),
+            tag_filter: String::new(),
+            filter_input: String::new(),
+            search: String::new(),
+            search_input: String::new(),
+            search_error: None,
             preferences: StatefulList::default(),
             preferences_input: String::new(),
+            preferences_error: None,
+            checklist: StatefulList::default(),
             cursor_pos_x: 0,
             cursor_pos_y: 0,
             width: 0,
-        })
+        }
     }
 
     pub fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> Result<()> {
@@ -73,72 +158,144 @@ impl App {
     }
 
     pub(crate) fn update_tasks(&mut self) -> Result<()> {
-        let mut tasks = self
-            .database
-            .iter()
-            .filter_map(|v| {
-                let (id, task) = v.ok()?;
+        let now = Local::now().naive_local();
 
-                let id = String::from_utf8_lossy(&id).to_string();
+        self.database.refresh(now)?;
 
-                let (task, _) =
-                    bincode::serde::decode_from_slice::<Task, _>(&task, self.database.config)
-                        .ok()?;
+        self.projects.items = vec![(None, "All tasks".to_string())];
+        self.projects.items.extend(
+            self.database
+                .projects()?
+                .into_iter()
+                .map(|(id, project)| (Some(id), project.name)),
+        );
 
-                if task.preferences.expire <= Local::now().naive_local() {
-                    return None;
-                }
+        match self.projects.state.selected() {
+            Some(i) if i < self.projects.items.len() => {}
+            _ => self.projects.state.select(Some(0)),
+        }
 
-                Some((id, Rc::new(RefCell::new(task))))
-            })
-            .collect::<Vec<(String, Rc<RefCell<Task>>)>>();
+        let mut query = TaskQuery {
+            states: self.view.states(),
+            project: self.project(),
+            sort: self.sort,
+            ..Default::default()
+        };
+
+        query.filter_tags(&self.tag_filter)?;
+        query.filter(&self.search)?;
 
-        tasks.sort_unstable_by(|(_, time1), (_, time2)| {
-            let time1 = time1.borrow();
-            let time2 = time2.borrow();
+        self.tasks.items = self
+            .database
+            .query_tasks(&query, now)?
+            .into_iter()
+            .map(|(id, task)| (id, Rc::new(RefCell::new(task))))
+            .collect::<Vec<(TaskId, Rc<RefCell<Task>>)>>();
+        self.blocked = self.database.blocked_tasks()?;
+
+        if let Some(i) = self.tasks.state.selected() {
+            if i >= self.tasks.items.len() {
+                self.tasks.state.select(self.tasks.items.len().checked_sub(1));
+            }
+        }
+
+        Ok(())
+    }
 
-            time1.creation_date.cmp(&time2.creation_date)
-        });
+    /// Loads the dependency chains of the selected task.
+    pub(crate) fn update_dependencies(&mut self) -> Result<()> {
+        let id = match self.task() {
+            Some((id, _)) => id.clone(),
+            None => return Ok(()),
+        };
+
+        self.upstream.items = self.database.upstream(&id)?;
+        self.downstream = self.database.downstream(&id)?;
 
-        self.tasks.items = tasks;
+        match self.upstream.state.selected() {
+            Some(i) if i < self.upstream.items.len() => {}
+            _ => self.upstream.state.select((!self.upstream.items.is_empty()).then_some(0)),
+        }
 
         Ok(())
     }
 
-    pub(crate) fn task(&self) -> Option<(&String, Rc<RefCell<Task>>)> {
+    pub(crate) fn task(&self) -> Option<(&TaskId, Rc<RefCell<Task>>)> {
         let (id, task) = self.tasks.items.get(self.tasks.state.selected()?)?;
 
         Some((id, task.clone()))
     }
 
+    /// The project whose tasks are listed, `None` for all tasks.
+    pub(crate) fn project(&self) -> Option<ProjectId> {
+        let (id, _) = self.projects.items.get(self.projects.state.selected()?)?;
+
+        id.clone()
+    }
+
     fn ui<B: Backend>(&mut self, f: &mut Frame<B>) {
         match self.mode {
-            WindowMode::List => self.tasks_window(f),
+            WindowMode::List | WindowMode::NewProject | WindowMode::Filter | WindowMode::Search => {
+                self.tasks_window(f)
+            }
             WindowMode::Task(_) => self.view_window(f),
             WindowMode::Preferences(_) => self.preferences_window(f),
             WindowMode::Stats => self.statistics_window(f),
+            WindowMode::Dependencies(_) => self.dependencies_window(f),
         }
     }
 
     fn tasks_window<B: Backend>(&mut self, f: &mut Frame<B>) {
         let layout = Layout::default()
+            .direction(Direction::Horizontal)
             .margin(2)
-            .constraints([Constraint::Percentage(100)])
+            .constraints([Constraint::Percentage(20), Constraint::Percentage(80)])
             .split(f.size());
 
+        self.projects_sidebar(f, layout[0]);
+
+        let now = Local::now().naive_local();
+
         let tasks: Vec<ListItem> = self
             .tasks
             .items
             .iter()
-            .map(|(_, t)| {
+            .map(|(id, t)| {
                 let t = t.clone();
                 let t = &mut *t.borrow_mut();
                 let (status, style) = if t.done {
                     ("✅ ".to_string(), Style::default().fg(Color::Green))
+                } else if self.blocked.contains(id) {
+                    ("🔒 ".to_string(), Style::default().fg(Color::DarkGray))
+                } else if t.state(now) == TaskState::Overdue {
+                    ("❌ ".to_string(), Style::default().fg(Color::Red))
                 } else {
                     ("❌ ".to_string(), Style::default())
                 };
-                let content = vec![Spans::from(Span::styled(status + &t.title, style))];
+                let mut content = vec![Span::styled(status, style)];
+
+                if let Some(marker) = priority_marker(t.priority) {
+                    content.push(marker);
+                }
+
+                content.push(Span::styled(t.title.clone(), style));
+
+                if let Some((done, total)) = t.progress() {
+                    content.push(Span::styled(
+                        format!("  {}/{}", done, total),
+                        Style::default().fg(Color::DarkGray),
+                    ));
+                }
+
+                for tag in &t.tags {
+                    content.push(Span::raw(" "));
+                    content.push(Span::styled(
+                        format!(" #{} ", tag),
+                        Style::default().fg(Color::Black).bg(tag_color(tag)),
+                    ));
+                }
+
+                let content = vec![Spans::from(content)];
                 ListItem::new(content)
             })
             .collect();
@@ -147,29 +304,126 @@ impl App {
             self.tasks.state.select(Some(0));
         }
 
+        let mut title = self.view.title().to_string();
+
+        if !self.tag_filter.is_empty() {
+            title.push_str(&format!("{} ", self.tag_filter));
+        }
+
+        if !self.search.is_empty() {
+            title.push_str(&format!("/{} ", self.search));
+        }
+
+        if self.sort != Sort::default() {
+            title.push_str(&format!("by {} ", self.sort));
+        }
+
         let tasks = List::new(tasks)
-            .block(Block::default().borders(Borders::ALL).title(" Tasks "))
+            .block(Block::default().borders(Borders::ALL).title(title))
             .highlight_style(
                 Style::default()
                     .bg(Color::DarkGray)
                     .add_modifier(Modifier::BOLD),
             );
 
-        f.render_stateful_widget(tasks, layout[0], &mut self.tasks.state)
+        let input = match self.mode {
+            WindowMode::Filter => Some((
+                self.filter_input.as_str(),
+                Span::raw(" Filter (#tag -#tag) "),
+            )),
+            WindowMode::Search => Some((
+                self.search_input.as_str(),
+                match &self.search_error {
+                    Some(error) => {
+                        Span::styled(format!(" {} ", error), Style::default().fg(Color::Red))
+                    }
+                    None => Span::raw(" Search (done:no due:<date tag:name \"text\" words) "),
+                },
+            )),
+            _ => None,
+        };
+
+        if let Some((text, title)) = input {
+            let area = Layout::default()
+                .constraints([Constraint::Min(3), Constraint::Length(3)])
+                .split(layout[1]);
+
+            let input = Paragraph::new(text).block(
+                Block::default()
+                    .title(title)
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded),
+            );
+
+            self.width = area[1].width;
+
+            f.set_cursor(area[1].x + text.width() as u16 + 1, area[1].y + 1);
+            f.render_stateful_widget(tasks, area[0], &mut self.tasks.state);
+            f.render_widget(input, area[1]);
+        } else {
+            f.render_stateful_widget(tasks, layout[1], &mut self.tasks.state)
+        }
+    }
+
+    fn projects_sidebar<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
+        let layout = Layout::default()
+            .constraints([Constraint::Min(3), Constraint::Length(3)])
+            .split(area);
+
+        let projects: Vec<ListItem> = self
+            .projects
+            .items
+            .iter()
+            .map(|(_, name)| ListItem::new(vec![Spans::from(Span::raw(name.as_str()))]))
+            .collect();
+
+        let projects = List::new(projects)
+            .block(Block::default().borders(Borders::ALL).title(" Projects "))
+            .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD));
+
+        if self.mode == WindowMode::NewProject {
+            let input = Paragraph::new(self.project_input.as_ref()).block(
+                Block::default()
+                    .title(" New project ")
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded),
+            );
+
+            self.width = layout[1].width;
+
+            f.set_cursor(
+                layout[1].x + self.project_input.width() as u16 + 1,
+                layout[1].y + 1,
+            );
+            f.render_stateful_widget(projects, layout[0], &mut self.projects.state);
+            f.render_widget(input, layout[1]);
+        } else {
+            f.render_stateful_widget(projects, area, &mut self.projects.state);
+        }
     }
 
     fn view_window<B: Backend>(&mut self, f: &mut Frame<B>) {
-        let (_, task) = self.task().unwrap();
+        let (id, task) = self.task().unwrap();
+        let id = id.clone();
         let task = &mut *task.borrow_mut();
 
-        self.cursor_pos_x = task.description.split('\n').last().unwrap().width() as u16;
+        self.cursor_pos_x = task.description.split('\n').next_back().unwrap().width() as u16;
 
         let layout = Layout::default()
             .margin(2)
             .constraints([Constraint::Percentage(7), Constraint::Percentage(93)])
             .split(f.size());
 
-        self.width = layout[1].width;
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(65), Constraint::Percentage(35)])
+            .split(layout[1]);
+
+        let side = Layout::default()
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(columns[1]);
+
+        self.width = columns[0].width;
 
         let title_block = Paragraph::new(task.title.as_ref())
             .style(match self.mode {
@@ -199,19 +453,111 @@ impl App {
                     .border_type(BorderType::Rounded),
             );
 
+        self.checklist.items = task
+            .subtasks
+            .iter()
+            .map(|subtask| {
+                let mut item = format!(
+                    "[{}] {}",
+                    if subtask.done { "x" } else { " " },
+                    subtask.title
+                );
+
+                if subtask.exp > 0 {
+                    item.push_str(&format!(" (+{} exp)", subtask.exp));
+                }
+
+                item
+            })
+            .collect();
+
+        let checklist = self
+            .checklist
+            .items
+            .iter()
+            .map(|item| ListItem::new(vec![Spans::from(Span::raw(item.as_str()))]))
+            .collect::<Vec<_>>();
+
+        let checklist_block = List::new(checklist)
+            .style(match self.mode {
+                WindowMode::Task(EditMode::Edit(EditState::Checklist | EditState::Subtask)) => {
+                    Style::default().fg(Color::Yellow)
+                }
+                _ => Style::default(),
+            })
+            .block(
+                Block::default()
+                    .title(match task.progress() {
+                        Some((done, total)) => format!(" Checklist {}/{} ", done, total),
+                        None => " Checklist ".to_string(),
+                    })
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded),
+            )
+            .highlight_style(match self.mode {
+                WindowMode::Task(EditMode::Edit(EditState::Checklist | EditState::Subtask)) => {
+                    Style::default()
+                        .bg(Color::DarkGray)
+                        .add_modifier(Modifier::BOLD)
+                }
+                _ => Style::default(),
+            });
+
+        let (history, history_error) = match self.database.task_history(&id) {
+            Ok(history) => (history, None),
+            Err(error) => (Vec::new(), Some(format!("{:#}", error))),
+        };
+        let history: Vec<ListItem> = history
+            .into_iter()
+            .rev()
+            .map(|entry| {
+                ListItem::new(vec![
+                    Spans::from(Span::styled(
+                        entry.time.format("%Y-%m-%d %H:%M").to_string(),
+                        Style::default().fg(Color::DarkGray),
+                    )),
+                    Spans::from(Span::raw(entry.event.to_string())),
+                ])
+            })
+            .collect();
+
+        let history_block = List::new(history).block(
+            Block::default()
+                .title(match history_error {
+                    Some(error) => {
+                        Span::styled(format!(" {} ", error), Style::default().fg(Color::Red))
+                    }
+                    None => Span::raw(" History "),
+                })
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded),
+        );
+
         match self.mode {
             WindowMode::Task(EditMode::Edit(EditState::Title)) => {
                 f.set_cursor(layout[0].x + task.title.width() as u16 + 1, layout[0].y + 1)
             }
             WindowMode::Task(EditMode::Edit(EditState::Task)) => f.set_cursor(
-                layout[1].x + self.cursor_pos_x + 1,
-                layout[1].y + self.cursor_pos_y + 1,
+                columns[0].x + self.cursor_pos_x + 1,
+                columns[0].y + self.cursor_pos_y + 1,
             ),
+            WindowMode::Task(EditMode::Edit(EditState::Subtask)) => {
+                if let Some(i) = self.checklist.state.selected() {
+                    let item = self.checklist.items.get(i).map_or(0, |item| item.width());
+
+                    f.set_cursor(
+                        side[0].x + item as u16 + 1,
+                        side[0].y + (i as u16).min(side[0].height.saturating_sub(3)) + 1,
+                    )
+                }
+            }
             _ => {}
         }
 
         f.render_widget(title_block, layout[0]);
-        f.render_widget(task_block, layout[1]);
+        f.render_widget(task_block, columns[0]);
+        f.render_stateful_widget(checklist_block, side[0], &mut self.checklist.state);
+        f.render_widget(history_block, side[1]);
     }
 
     fn preferences_window<B: Backend>(&mut self, f: &mut Frame<B>) {
@@ -226,12 +572,38 @@ impl App {
         let task = &mut *task.borrow_mut();
 
         self.preferences.items = vec![
-            format!("Repeat: {}", task.preferences.daily_repeat),
+            format!(
+                "Repeat: {}",
+                task.preferences
+                    .repeat
+                    .as_ref()
+                    .map_or("none".to_string(), |r| r.to_string())
+            ),
             format!(
                 "Expire: {}",
                 task.preferences.expire.format("%Y-%m-%d %H:%M:%S")
             ),
             format!("Experience: {}", task.preferences.exp),
+            format!(
+                "Auto-complete: {}",
+                if task.preferences.auto_complete { "yes" } else { "no" }
+            ),
+            format!(
+                "Tags: {}",
+                task.tags
+                    .iter()
+                    .map(|tag| format!("#{}", tag))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            format!(
+                "Project: {}",
+                task.project
+                    .as_ref()
+                    .and_then(|id| self.projects.items.iter().find(|(p, _)| p.as_ref() == Some(id)))
+                    .map_or("none", |(_, name)| name.as_str())
+            ),
+            format!("Priority: {}", task.priority),
         ];
 
         let options: Vec<ListItem> = self
@@ -247,9 +619,28 @@ impl App {
                 .add_modifier(Modifier::BOLD),
         );
 
+        let selected = self.preferences.state.selected();
+        let title = match (&self.preferences_error, selected, self.preferences_input.trim()) {
+            (Some(error), _, _) => {
+                Span::styled(format!(" {} ", error), Style::default().fg(Color::Red))
+            }
+            // Deadlines are previewed while typing, the input being hard to guess
+            (None, Some(1), input)
+                if self.mode == WindowMode::Preferences(true) && !input.is_empty() =>
+            {
+                match parse_deadline(input, Local::now().naive_local()) {
+                    Ok(date) => Span::raw(format!(" Edit → {} ", date.format("%a %Y-%m-%d %H:%M:%S"))),
+                    Err(error) => {
+                        Span::styled(format!(" {} ", error), Style::default().fg(Color::Red))
+                    }
+                }
+            }
+            _ => Span::raw(" Edit "),
+        };
+
         let input = Paragraph::new(self.preferences_input.as_ref()).block(
             Block::default()
-                .title(" Edit ")
+                .title(title)
                 .borders(Borders::ALL)
                 .border_type(BorderType::Rounded),
         );
@@ -265,6 +656,92 @@ impl App {
         f.render_widget(input, layout[1]);
     }
 
+    fn dependencies_window<B: Backend>(&mut self, f: &mut Frame<B>) {
+        let (_, task) = self.task().unwrap();
+        let title = task.borrow().title.clone();
+        let adding = self.mode == WindowMode::Dependencies(true);
+
+        let layout = Layout::default()
+            .margin(2)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Min(3),
+                Constraint::Length(if adding { 3 } else { 0 }),
+            ])
+            .split(f.size());
+
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(layout[1]);
+
+        let item = |link: &ChainLink| {
+            let (status, style) = if link.task.done || link.task.archived.is_some() {
+                ("✅ ", Style::default().fg(Color::Green))
+            } else {
+                ("❌ ", Style::default())
+            };
+
+            ListItem::new(vec![Spans::from(Span::styled(
+                format!("{}{}{}", "  ".repeat(link.depth - 1), status, link.task.title),
+                style,
+            ))])
+        };
+
+        let upstream = List::new(self.upstream.items.iter().map(item).collect::<Vec<_>>())
+            .block(
+                Block::default()
+                    .title(" Waits for ")
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded),
+            )
+            .highlight_style(
+                Style::default()
+                    .bg(Color::DarkGray)
+                    .add_modifier(Modifier::BOLD),
+            );
+
+        let downstream = List::new(self.downstream.iter().map(item).collect::<Vec<_>>()).block(
+            Block::default()
+                .title(" Blocks ")
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded),
+        );
+
+        let title = Paragraph::new(title).block(
+            Block::default()
+                .title(" Dependencies ")
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded),
+        );
+
+        f.render_widget(title, layout[0]);
+        f.render_stateful_widget(upstream, columns[0], &mut self.upstream.state);
+        f.render_widget(downstream, columns[1]);
+
+        if adding {
+            let input = Paragraph::new(self.dependency_input.as_ref()).block(
+                Block::default()
+                    .title(match &self.dependency_error {
+                        Some(error) => {
+                            Span::styled(format!(" {} ", error), Style::default().fg(Color::Red))
+                        }
+                        None => Span::raw(" Waits for (search) "),
+                    })
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded),
+            );
+
+            self.width = layout[2].width;
+
+            f.set_cursor(
+                layout[2].x + self.dependency_input.width() as u16 + 1,
+                layout[2].y + 1,
+            );
+            f.render_widget(input, layout[2]);
+        }
+    }
+
     fn statistics_window<B: Backend>(&mut self, f: &mut Frame<B>) {
         let layout = Layout::default()
             .margin(2)
@@ -273,18 +750,36 @@ impl App {
 
         let username = env::var("USER").unwrap_or_default();
 
+        let now = Local::now().naive_local();
         let account = self.database.account().unwrap_or_default();
-        let next_lvl_exp = 10*(account.lvl+2).pow(2);
+        let streak = self.database.streak(now).unwrap_or_default();
+        let multipliers = self.database.exp_multipliers().unwrap_or_default();
 
-        let stats = format!(
+        let mut stats = format!(
             "Level: {}\n\
             Exp: {}\n\
-            Exp to next level: {}",
+            Exp to next level: {}\n\
+            Streak: {} days\n\
+            Priority multipliers (m): {}",
             account.lvl,
             account.exp,
-            next_lvl_exp - account.exp,
+            account.next_level_exp(),
+            streak,
+            if multipliers { "on" } else { "off" },
         );
 
+        for (id, name) in self.projects.items.iter().skip(1) {
+            if let Some(project) = id
+                .as_ref()
+                .and_then(|id| self.database.project_stats(id, now).ok())
+            {
+                stats.push_str(&format!(
+                    "\n\n{}: {}/{} done, {} overdue, {} archived, {} exp",
+                    name, project.done, project.tasks, project.overdue, project.archived, project.exp
+                ));
+            }
+        }
+
         let stats = Paragraph::new(stats).block(
             Block::default()
                 .title(format!(" {}'s stats", &username))
@@ -297,6 +792,39 @@ impl App {
     }
 }
 
+/// `!` marks in the list row, one more for each priority level.
+fn priority_marker(priority: Priority) -> Option<Span<'static>> {
+    let color = match priority {
+        Priority::None => return None,
+        Priority::Low => Color::DarkGray,
+        Priority::Medium => Color::Yellow,
+        Priority::High => Color::LightRed,
+        Priority::Urgent => Color::Red,
+    };
+    let level = Priority::ALL.iter().position(|p| *p == priority).unwrap_or_default();
+
+    Some(Span::styled(
+        format!("{} ", "!".repeat(level)),
+        Style::default().fg(color).add_modifier(Modifier::BOLD),
+    ))
+}
+
+/// A color for the chip of a tag, the same for every run.
+fn tag_color(tag: &str) -> Color {
+    const COLORS: [Color; 6] = [
+        Color::Cyan,
+        Color::Green,
+        Color::Yellow,
+        Color::Magenta,
+        Color::LightBlue,
+        Color::LightRed,
+    ];
+
+    let hash = tag.bytes().fold(0usize, |hash, b| hash.wrapping_mul(31).wrapping_add(b as usize));
+
+    COLORS[hash % COLORS.len()]
+}
+
 #[derive(Default)]
 pub(crate) struct StatefulList<T> {
     pub(crate) state: ListState,